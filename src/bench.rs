@@ -3,7 +3,7 @@ use std::num::NonZeroUsize;
 
 use orfail::OrFail;
 
-use crate::types::{Request, Response, ServerAddr};
+use crate::types::{Request, RequestId, Response, ServerAddr};
 
 pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
     if !noargs::cmd("bench")
@@ -17,7 +17,7 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
     let concurrency: NonZeroUsize = noargs::opt("concurrency")
         .short('c')
         .ty("INTEGER")
-        .doc("Number of concurrent requests")
+        .doc("Number of concurrent requests (a batch request counts as one)")
         .default("1")
         .take(args)
         .then(|o| o.value().parse())?;
@@ -111,15 +111,18 @@ impl BenchCommand {
             let line = line.or_fail()?;
             let request = Request::parse(line).or_fail()?;
 
-            let id = request.id.as_ref().or_fail_with(|()| {
+            request.has_response().or_fail_with(|()| {
                 format!(
                     "bench command does not support notification: {}",
                     request.json
                 )
             })?;
-            (!ids.contains(id))
-                .or_fail_with(|()| format!("Request contains duplicate ID: {}", request.json))?;
-            ids.insert(id.clone());
+            for id in request.id.iter().chain(request.batch_ids.iter().flatten()) {
+                (!ids.contains(id)).or_fail_with(|()| {
+                    format!("Request contains duplicate ID: {}", request.json)
+                })?;
+                ids.insert(id.clone());
+            }
 
             self.requests.push(request);
         }
@@ -183,57 +186,62 @@ impl BenchCommand {
         let mut output_writer = std::io::BufWriter::new(stdout.lock());
 
         for channel in &self.channels {
-            let mut requests = channel
-                .requests
-                .iter()
-                .zip(channel.start_times.iter())
-                .map(|(r, t)| (r.id.clone(), (r, *t)))
-                .collect::<std::collections::HashMap<_, _>>();
+            // Each element of a batch request is matched with the response having the same ID
+            let mut requests = std::collections::HashMap::new();
+            for (request, start_time) in channel.requests.iter().zip(channel.start_times.iter()) {
+                for object in request.objects() {
+                    if let Some(id) = object.to_member("id").or_fail()?.get() {
+                        let id = RequestId::parse(id).or_fail()?;
+                        requests.insert(id, (object, *start_time));
+                    }
+                }
+            }
 
             for (line, end_time) in std::io::BufReader::new(&channel.recv_buf[..])
                 .lines()
                 .zip(channel.end_times.iter())
             {
                 let line = line.or_fail()?;
-                let mut response = Response::parse(line).or_fail()?;
-                let id = response
-                    .id
-                    .take()
-                    .or_fail_with(|()| "Response missing required 'id' field".to_owned())?;
-                let (request, start_time) = requests.remove(&Some(id)).or_fail_with(|()| {
-                    "Response ID does not match any pending request".to_owned()
-                })?;
-                let start_unix_timestamp =
-                    start_time.duration_since(self.base_time) + self.base_unix_timestamp;
-                let end_unix_timestamp =
-                    end_time.duration_since(self.base_time) + self.base_unix_timestamp;
-
-                writeln!(
-                    output_writer,
-                    "{}",
-                    nojson::object(|f| {
-                        for (name, value) in request.json.value().to_object().expect("bug") {
-                            let name = name.as_string_str().expect("infallible");
-                            f.member(name, value)?;
-                        }
-                        for (name, value) in response.json.value().to_object().expect("bug") {
-                            let name = name.as_string_str().expect("infallible");
-                            if !matches!(name, "jsonrpc" | "id") {
+                let response = Response::parse(line).or_fail()?;
+                for (response, id) in response.objects().zip(&response.ids) {
+                    let id = id
+                        .as_ref()
+                        .or_fail_with(|()| "Response missing required 'id' field".to_owned())?;
+                    let (request, start_time) = requests.remove(id).or_fail_with(|()| {
+                        "Response ID does not match any pending request".to_owned()
+                    })?;
+                    let start_unix_timestamp =
+                        start_time.duration_since(self.base_time) + self.base_unix_timestamp;
+                    let end_unix_timestamp =
+                        end_time.duration_since(self.base_time) + self.base_unix_timestamp;
+
+                    writeln!(
+                        output_writer,
+                        "{}",
+                        nojson::object(|f| {
+                            for (name, value) in request.to_object().expect("bug") {
+                                let name = name.as_string_str().expect("infallible");
                                 f.member(name, value)?;
                             }
-                        }
-                        f.member("server", &channel.server_addr.0)?;
-                        f.member("request_byte_size", request.json.text().len())?;
-                        f.member("response_byte_size", response.json.text().len())?;
-                        f.member(
-                            "start_unix_timestamp_micros",
-                            start_unix_timestamp.as_micros(),
-                        )?;
-                        f.member("end_unix_timestamp_micros", end_unix_timestamp.as_micros())?;
-                        Ok(())
-                    })
-                )
-                .or_fail()?;
+                            for (name, value) in response.to_object().expect("bug") {
+                                let name = name.as_string_str().expect("infallible");
+                                if !matches!(name, "jsonrpc" | "id") {
+                                    f.member(name, value)?;
+                                }
+                            }
+                            f.member("server", &channel.server_addr.0)?;
+                            f.member("request_byte_size", request.as_raw_str().len())?;
+                            f.member("response_byte_size", response.as_raw_str().len())?;
+                            f.member(
+                                "start_unix_timestamp_micros",
+                                start_unix_timestamp.as_micros(),
+                            )?;
+                            f.member("end_unix_timestamp_micros", end_unix_timestamp.as_micros())?;
+                            Ok(())
+                        })
+                    )
+                    .or_fail()?;
+                }
             }
         }
 
@@ -77,7 +77,7 @@ impl CallCommand {
             writeln!(rpc_writer, "{}", request.json).or_fail()?;
             rpc_writer.flush().or_fail()?;
 
-            if request.id.is_some() {
+            if request.has_response() {
                 let mut response_line = String::new();
                 let bytes_read = rpc_reader.read_line(&mut response_line).or_fail()?;
                 (bytes_read > 0).or_fail_with(|()| {
@@ -110,7 +110,7 @@ impl CallCommand {
 
             socket.send(request.json.text().as_bytes()).or_fail()?;
 
-            if request.id.is_some() {
+            if request.has_response() {
                 let mut buf = vec![0u8; MAX_UDP_PACKET];
                 let bytes_read = socket.recv(&mut buf).or_fail()?;
                 (bytes_read > 0).or_fail_with(|()| {
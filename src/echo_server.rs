@@ -1,9 +1,18 @@
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::{TcpStream, UdpSocket};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::net::UdpSocket;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::{Duration, Instant};
 
 use orfail::OrFail;
 
-use crate::types::ServerAddr;
+use crate::rng::Rng;
+use crate::types::{Probability, ServerAddr};
 
 pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
     if !noargs::cmd("echo-server")
@@ -11,7 +20,10 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
             "Run a JSON-RPC echo server (for development or testing purposes)\n",
             "\n",
             "This server will respond to every request with a response containing\n",
-            "the same request object as the result value."
+            "the same request object as the result value.\n",
+            "\n",
+            "On SIGINT or SIGTERM, the server finishes in-flight requests and prints\n",
+            "the statistics of the handled messages to the standard error."
         ))
         .take(args)
         .is_present()
@@ -24,6 +36,138 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
         .doc("Use UDP instead of TCP (one packet per request/response)")
         .take(args)
         .is_present();
+    let concurrent: bool = noargs::flag("concurrent")
+        .short('c')
+        .doc(concat!(
+            "Process requests on each TCP connection concurrently\n",
+            "\n",
+            "Responses are sent in the order of completion instead of the order of requests.\n",
+            "Combined with random delays (e.g., --delay uniform:0:0.01),\n",
+            "this makes responses arrive out of order."
+        ))
+        .take(args)
+        .is_present();
+    let mut options = ServerOptions {
+        concurrent,
+        ..ServerOptions::default()
+    };
+    let delay_opt = noargs::opt("delay").ty("[METHOD=]DELAY").doc(concat!(
+        "Delay responses (to the requests of METHOD if specified)\n",
+        "\n",
+        "DELAY is one of SECONDS (fixed), uniform:MIN_SECONDS:MAX_SECONDS\n",
+        "or exp:MEAN_SECONDS (exponential distribution).\n",
+        "This option can be specified multiple times."
+    ));
+    while let Some(v) = delay_opt
+        .take(args)
+        .present_and_then(|o| o.value().parse())?
+    {
+        options.faults.delays.push(v);
+    }
+    let error_rate_opt = noargs::opt("error-rate")
+        .ty("[METHOD=]PROBABILITY")
+        .doc(concat!(
+            "Probability of returning a JSON-RPC error instead of the result\n",
+            "(to the requests of METHOD if specified)"
+        ));
+    while let Some(v) = error_rate_opt
+        .take(args)
+        .present_and_then(|o| o.value().parse())?
+    {
+        options.faults.error_rates.push(v);
+    }
+    let drop_rate_opt = noargs::opt("drop-rate")
+        .ty("[METHOD=]PROBABILITY")
+        .doc(concat!(
+            "Probability of dropping the response\n",
+            "(to the requests of METHOD if specified)"
+        ));
+    while let Some(v) = drop_rate_opt
+        .take(args)
+        .present_and_then(|o| o.value().parse())?
+    {
+        options.faults.drop_rates.push(v);
+    }
+    let close_rate_opt = noargs::opt("close-rate")
+        .ty("[METHOD=]PROBABILITY")
+        .doc(concat!(
+            "Probability of closing the connection instead of responding\n",
+            "(to the requests of METHOD if specified; TCP only)"
+        ));
+    while let Some(v) = close_rate_opt
+        .take(args)
+        .present_and_then(|o| o.value().parse())?
+    {
+        options.faults.close_rates.push(v);
+    }
+    let notify_method: String = noargs::opt("notify-method")
+        .ty("METHOD")
+        .doc("Method name of the notifications pushed by --notify-interval or --subscribe-method")
+        .default("notify")
+        .take(args)
+        .then(|o| o.value().parse())?;
+    let notify_params: Option<nojson::RawJsonOwned> = noargs::opt("notify-params")
+        .ty("OBJECT | ARRAY")
+        .doc("Parameters of the pushed notifications (JSON array or JSON object)")
+        .take(args)
+        .present_and_then(|a| {
+            let json = nojson::RawJson::parse(a.value())?;
+            if !matches!(
+                json.value().kind(),
+                nojson::JsonValueKind::Array | nojson::JsonValueKind::Object
+            ) {
+                return Err(json.value().invalid("must be a JSON array or JSON object"));
+            }
+            Ok(json.into_owned())
+        })?;
+    options.notifications.notification = nojson::object(|f| {
+        f.member("jsonrpc", "2.0")?;
+        f.member("method", &notify_method)?;
+        if let Some(params) = &notify_params {
+            f.member("params", params)?;
+        }
+        Ok(())
+    })
+    .to_string();
+    options.notifications.interval = noargs::opt("notify-interval")
+        .ty("SECONDS")
+        .doc("Push a notification to each TCP connection at this interval")
+        .take(args)
+        .present_and_then(|o| {
+            parse_seconds(o.value()).and_then(|d| {
+                if d.is_zero() {
+                    Err("interval must be positive".to_owned())
+                } else {
+                    Ok(d)
+                }
+            })
+        })?;
+    options.notifications.subscribe_method = noargs::opt("subscribe-method")
+        .ty("METHOD")
+        .doc("Push notifications before responding to the requests of this method")
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    options.notifications.subscribe_count = noargs::opt("subscribe-count")
+        .ty("INTEGER")
+        .doc("Number of notifications pushed before responding to --subscribe-method requests")
+        .default("1")
+        .take(args)
+        .then(|o| o.value().parse())?;
+    let workers: Option<NonZeroUsize> = noargs::opt("workers")
+        .short('w')
+        .ty("INTEGER")
+        .doc("Number of worker threads serving TCP connections (default: available CPUs)")
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    if let Some(workers) = workers {
+        options.workers = workers;
+    }
+    options.shutdown_timeout = noargs::opt("shutdown-timeout")
+        .ty("SECONDS")
+        .doc("Maximum time to wait for in-flight requests to complete on SIGINT or SIGTERM")
+        .default("5")
+        .take(args)
+        .then(|o| parse_seconds(o.value()))?;
     let listen_addr: ServerAddr = noargs::arg("<ADDR>")
         .doc("Listen address")
         .example("127.0.0.1:8080")
@@ -34,101 +178,886 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
         return Ok(true);
     }
 
+    let handler = Arc::new(EchoHandler);
     if use_udp {
-        run_server_udp(listen_addr)?;
+        run_server_udp(listen_addr, handler, options)?;
     } else {
-        run_server_tcp(listen_addr)?;
+        run_server_tcp(listen_addr, handler, options)?;
     }
     Ok(true)
 }
 
-fn run_server_tcp(listen_addr: ServerAddr) -> orfail::Result<()> {
-    let listener = std::net::TcpListener::bind(listen_addr.0).or_fail()?;
-    for incoming in listener.incoming() {
-        let stream = incoming.or_fail()?;
-        std::thread::spawn(move || {
-            let _ = handle_client(stream);
+/// Builds the response for each JSON-RPC request (i.e., a message that has an "id" member).
+pub(crate) trait RequestHandler: 'static + Send + Sync {
+    fn handle_request(
+        &self,
+        request: nojson::RawJsonValue<'_, '_>,
+        request_id: nojson::RawJsonValue<'_, '_>,
+    ) -> String;
+}
+
+#[derive(Debug)]
+struct EchoHandler;
+
+impl RequestHandler for EchoHandler {
+    fn handle_request(
+        &self,
+        request: nojson::RawJsonValue<'_, '_>,
+        request_id: nojson::RawJsonValue<'_, '_>,
+    ) -> String {
+        let response = nojson::object(|f| {
+            f.member("jsonrpc", "2.0")?;
+            f.member("id", request_id)?;
+            f.member("result", request)
         });
+        response.to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ServerOptions {
+    pub workers: NonZeroUsize,
+    pub concurrent: bool,
+    pub faults: FaultInjection,
+    pub notifications: PushNotifications,
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            workers: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            concurrent: false,
+            faults: FaultInjection::default(),
+            notifications: PushNotifications::default(),
+            shutdown_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Runs a TCP server until SIGINT or SIGTERM is received,
+/// and then prints the statistics of the server to the standard error.
+pub(crate) fn run_server_tcp<H: RequestHandler>(
+    listen_addr: ServerAddr,
+    handler: Arc<H>,
+    options: ServerOptions,
+) -> orfail::Result<()> {
+    const LISTENER_TOKEN: mio::Token = mio::Token(0);
+
+    install_shutdown_signal_handlers();
+
+    let listener = std::net::TcpListener::bind(listen_addr.0).or_fail()?;
+    listener.set_nonblocking(true).or_fail()?;
+    let mut listener = mio::net::TcpListener::from_std(listener);
+    let mut poll = mio::Poll::new().or_fail()?;
+    poll.registry()
+        .register(&mut listener, LISTENER_TOKEN, mio::Interest::READABLE)
+        .or_fail()?;
+
+    let options = Arc::new(options);
+    let mut workers = Vec::new();
+    for _ in 0..options.workers.get() {
+        let worker_poll = mio::Poll::new().or_fail()?;
+        let waker = mio::Waker::new(worker_poll.registry(), WAKER_TOKEN).or_fail()?;
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::new(worker_poll, rx, handler.clone(), options.clone());
+        let handle = std::thread::spawn(move || worker.run());
+        workers.push((tx, waker, handle));
     }
+
+    let mut stats = ServerStats::default();
+    let mut events = mio::Events::with_capacity(16);
+    while !is_shutdown_requested() {
+        match poll.poll(&mut events, Some(SHUTDOWN_CHECK_INTERVAL)) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => result.or_fail()?,
+        }
+        loop {
+            let stream = match listener.accept() {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // e.g., EMFILE: the pending connection is retried after the next poll
+                    eprintln!("Failed to accept connection: {e}");
+                    break;
+                }
+                Ok((stream, _)) => stream,
+            };
+            if let Err(e) = stream.set_nodelay(true) {
+                eprintln!("Failed to set TCP_NODELAY: {e}");
+            }
+
+            let (tx, waker, _) = &workers[stats.connections as usize % workers.len()];
+            tx.send(stream).or_fail()?;
+            waker.wake().or_fail()?;
+            stats.connections += 1;
+        }
+    }
+
+    // Stop accepting new connections and wait for the workers to drain the existing ones
+    std::mem::drop(listener);
+    for (_, waker, _) in &workers {
+        waker.wake().or_fail()?;
+    }
+    for (_, _, handle) in workers {
+        let worker_stats = handle
+            .join()
+            .map_err(|_| orfail::Failure::new("Worker thread panicked"))?
+            .or_fail()?;
+        stats.merge(worker_stats);
+    }
+
+    eprintln!("{}", nojson::Json(&stats));
     Ok(())
 }
 
-fn run_server_udp(listen_addr: ServerAddr) -> orfail::Result<()> {
+/// Runs a UDP server until SIGINT or SIGTERM is received,
+/// and then prints the statistics of the server to the standard error.
+pub(crate) fn run_server_udp<H: RequestHandler>(
+    listen_addr: ServerAddr,
+    handler: Arc<H>,
+    options: ServerOptions,
+) -> orfail::Result<()> {
     const MAX_UDP_PACKET: usize = 65535;
 
+    install_shutdown_signal_handlers();
+
     let socket = UdpSocket::bind(listen_addr.0).or_fail()?;
+    socket
+        .set_read_timeout(Some(SHUTDOWN_CHECK_INTERVAL))
+        .or_fail()?;
     let mut buf = vec![0u8; MAX_UDP_PACKET];
-    loop {
-        let (bytes_read, peer_addr) = socket.recv_from(&mut buf).or_fail()?;
+    let mut rng = Rng::from_time();
+    let mut stats = ServerStats::default();
+    while !is_shutdown_requested() {
+        let (bytes_read, peer_addr) = match socket.recv_from(&mut buf) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue;
+            }
+            result => result.or_fail()?,
+        };
         if bytes_read == 0 {
             continue;
         }
+        stats.bytes_in += bytes_read as u64;
 
-        let response = match String::from_utf8(buf[..bytes_read].to_vec()) {
-            Ok(text) => match nojson::RawJson::parse(&text) {
-                Ok(json) => {
-                    let json_value = json.value();
-                    match parse_request(json_value) {
-                        Ok(Some(request_id)) => {
-                            let response = nojson::object(|f| {
-                                f.member("jsonrpc", "2.0")?;
-                                f.member("id", request_id)?;
-                                f.member("result", json_value)
-                            });
-                            Some(response.to_string())
-                        }
-                        Ok(None) => None,
-                        Err(e) => Some(build_error_response(e.to_string())),
+        let mut context = MessageContext {
+            handler: &*handler,
+            options: &options,
+            rng: &mut rng,
+            stats: &mut stats,
+            draining: false,
+        };
+        let reply = handle_message(&buf[..bytes_read], &mut context);
+
+        // NOTE: Connections cannot be closed in UDP, so `reply.close` is ignored here.
+        // Besides, delays block the whole server as this loop is single-threaded.
+        std::thread::sleep(reply.delay);
+        let notification = &options.notifications.notification;
+        let messages =
+            std::iter::repeat_n(notification, reply.notifications).chain(reply.response.as_ref());
+        for message in messages {
+            if socket.send_to(message.as_bytes(), peer_addr).is_ok() {
+                stats.bytes_out += message.len() as u64;
+            }
+        }
+        stats.pushed_notifications += reply.notifications as u64;
+    }
+
+    eprintln!("{}", nojson::Json(&stats));
+    Ok(())
+}
+
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_shutdown_signal(signal: libc::c_int) {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+
+    // Restore the default behavior so that a second signal terminates the process immediately
+    // SAFETY: `signal()` is async-signal-safe.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+fn install_shutdown_signal_handlers() {
+    let handler = handle_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: The handler only performs async-signal-safe operations.
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+fn is_shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Statistics of the messages handled by a server.
+#[derive(Debug, Default)]
+struct ServerStats {
+    connections: u64,
+    requests: u64,
+    notifications: u64,
+    pushed_notifications: u64,
+    errors: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    methods: HashMap<String, u64>,
+}
+
+impl ServerStats {
+    fn increment_method_count(&mut self, method: &str) {
+        if let Some(count) = self.methods.get_mut(method) {
+            *count += 1;
+        } else {
+            self.methods.insert(method.to_owned(), 1);
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.connections += other.connections;
+        self.requests += other.requests;
+        self.notifications += other.notifications;
+        self.pushed_notifications += other.pushed_notifications;
+        self.errors += other.errors;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        for (method, count) in other.methods {
+            *self.methods.entry(method).or_default() += count;
+        }
+    }
+}
+
+impl nojson::DisplayJson for ServerStats {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        f.set_indent_size(2);
+        f.set_spacing(true);
+        f.object(|f| {
+            f.member("connections", self.connections)?;
+            f.member("requests", self.requests)?;
+            f.member("notifications", self.notifications)?;
+            f.member("pushed_notifications", self.pushed_notifications)?;
+            f.member("errors", self.errors)?;
+            f.member("bytes_in", self.bytes_in)?;
+            f.member("bytes_out", self.bytes_out)?;
+            f.member("methods", self.methods.iter().collect::<BTreeMap<_, _>>())
+        })
+    }
+}
+
+const WAKER_TOKEN: mio::Token = mio::Token(usize::MAX);
+
+/// Size of unsent replies above which no more requests are read from the connection.
+const SEND_BUF_HIGH_WATER_MARK: usize = 1024 * 1024;
+
+/// Event loop that serves the TCP connections assigned to a thread.
+struct Worker<H> {
+    poll: mio::Poll,
+    incoming: mpsc::Receiver<mio::net::TcpStream>,
+    handler: Arc<H>,
+    options: Arc<ServerOptions>,
+    connections: Vec<Option<Connection>>,
+    free_tokens: Vec<mio::Token>,
+    timers: BinaryHeap<Reverse<(Instant, mio::Token)>>,
+    rng: Rng,
+    stats: ServerStats,
+    shutdown_deadline: Option<Instant>,
+}
+
+impl<H: RequestHandler> Worker<H> {
+    fn new(
+        poll: mio::Poll,
+        incoming: mpsc::Receiver<mio::net::TcpStream>,
+        handler: Arc<H>,
+        options: Arc<ServerOptions>,
+    ) -> Self {
+        Self {
+            poll,
+            incoming,
+            handler,
+            options,
+            connections: Vec::new(),
+            free_tokens: Vec::new(),
+            timers: BinaryHeap::new(),
+            rng: Rng::from_time(),
+            stats: ServerStats::default(),
+            shutdown_deadline: None,
+        }
+    }
+
+    fn run(mut self) -> orfail::Result<ServerStats> {
+        let mut events = mio::Events::with_capacity(1024);
+        loop {
+            if self.shutdown_deadline.is_none() && is_shutdown_requested() {
+                self.start_draining();
+            }
+            if let Some(deadline) = self.shutdown_deadline
+                && (self.connections.iter().all(|c| c.is_none()) || deadline <= Instant::now())
+            {
+                return Ok(self.stats);
+            }
+
+            let now = Instant::now();
+            let timeout = self
+                .timers
+                .peek()
+                .map(|Reverse((t, _))| *t)
+                .into_iter()
+                .chain(self.shutdown_deadline)
+                .min()
+                .map(|t| t.saturating_duration_since(now));
+            match self.poll.poll(&mut events, timeout) {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => result.or_fail()?,
+            }
+
+            for event in &events {
+                if event.token() == WAKER_TOKEN {
+                    self.accept_connections().or_fail()?;
+                } else {
+                    self.handle_connection_event(event.token());
+                }
+            }
+
+            let now = Instant::now();
+            while let Some(Reverse((time, token))) = self.timers.peek().copied()
+                && time <= now
+            {
+                self.timers.pop();
+                self.handle_connection_event(token);
+            }
+        }
+    }
+
+    /// Stops reading new requests and closes each connection once its in-flight requests are completed.
+    fn start_draining(&mut self) {
+        self.shutdown_deadline = Some(Instant::now() + self.options.shutdown_timeout);
+        for i in 0..self.connections.len() {
+            self.handle_connection_event(mio::Token(i));
+        }
+    }
+
+    fn accept_connections(&mut self) -> orfail::Result<()> {
+        while let Ok(mut stream) = self.incoming.try_recv() {
+            let token = self.free_tokens.pop().unwrap_or_else(|| {
+                self.connections.push(None);
+                mio::Token(self.connections.len() - 1)
+            });
+            if let Err(e) =
+                self.poll
+                    .registry()
+                    .register(&mut stream, token, mio::Interest::READABLE)
+            {
+                eprintln!("Failed to register connection: {e}");
+                self.free_tokens.push(token);
+                continue;
+            }
+
+            let mut connection = Connection::new(stream);
+            connection.next_notification_time = self
+                .options
+                .notifications
+                .interval
+                .map(|interval| Instant::now() + interval);
+            connection.schedule_timer(token, &mut self.timers);
+            self.connections[token.0] = Some(connection);
+        }
+        Ok(())
+    }
+
+    fn handle_connection_event(&mut self, token: mio::Token) {
+        let Some(connection) = self.connections.get_mut(token.0).and_then(|c| c.as_mut()) else {
+            // The connection has already been closed
+            return;
+        };
+        let mut context = MessageContext {
+            handler: &*self.handler,
+            options: &self.options,
+            rng: &mut self.rng,
+            stats: &mut self.stats,
+            draining: self.shutdown_deadline.is_some(),
+        };
+        match connection.handle_io(&mut self.poll, token, &mut context) {
+            Ok(true) => connection.schedule_timer(token, &mut self.timers),
+            Ok(false) | Err(_) => {
+                if let Some(mut connection) = self.connections[token.0].take() {
+                    let _ = self.poll.registry().deregister(&mut connection.stream);
+                }
+                self.free_tokens.push(token);
+            }
+        }
+    }
+}
+
+struct MessageContext<'a, H> {
+    handler: &'a H,
+    options: &'a ServerOptions,
+    rng: &'a mut Rng,
+    stats: &'a mut ServerStats,
+    draining: bool,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: mio::net::TcpStream,
+    recv_buf: Vec<u8>,
+    send_buf: Vec<u8>,
+    send_buf_offset: usize,
+    writable: bool,
+    eof: bool,
+    pending_replies: VecDeque<(Instant, Reply)>,
+    next_notification_time: Option<Instant>,
+    timer: Option<Instant>,
+}
+
+impl Connection {
+    fn new(stream: mio::net::TcpStream) -> Self {
+        Self {
+            stream,
+            recv_buf: Vec::new(),
+            send_buf: Vec::new(),
+            send_buf_offset: 0,
+            writable: false,
+            eof: false,
+            pending_replies: VecDeque::new(),
+            next_notification_time: None,
+            timer: None,
+        }
+    }
+
+    fn next_reply_time(&self) -> Option<Instant> {
+        self.pending_replies.front().map(|(time, _)| *time)
+    }
+
+    fn schedule_timer(
+        &mut self,
+        token: mio::Token,
+        timers: &mut BinaryHeap<Reverse<(Instant, mio::Token)>>,
+    ) {
+        let Some(time) = [self.next_reply_time(), self.next_notification_time]
+            .into_iter()
+            .flatten()
+            .min()
+        else {
+            return;
+        };
+        if self.timer != Some(time) {
+            timers.push(Reverse((time, token)));
+            self.timer = Some(time);
+        }
+    }
+
+    /// Returns `false` if the connection should be closed.
+    fn handle_io<H: RequestHandler>(
+        &mut self,
+        poll: &mut mio::Poll,
+        token: mio::Token,
+        context: &mut MessageContext<'_, H>,
+    ) -> orfail::Result<bool> {
+        // Stop reading while the client is not reading the replies
+        let paused = self.is_send_buf_full();
+        if !paused {
+            self.recv_messages(context).or_fail()?;
+        }
+        if context.draining {
+            self.next_notification_time = None;
+        }
+
+        let now = Instant::now();
+        let notification = &context.options.notifications.notification;
+        while self.next_reply_time().is_some_and(|time| time <= now) {
+            let (_, reply) = self.pending_replies.pop_front().or_fail()?;
+            if reply.close {
+                let _ = self.send(context.stats);
+                return Ok(false);
+            }
+            for _ in 0..reply.notifications {
+                self.enqueue_message(notification);
+            }
+            context.stats.pushed_notifications += reply.notifications as u64;
+            if let Some(response) = reply.response {
+                self.enqueue_message(&response);
+            }
+        }
+        if let Some(time) = self.next_notification_time
+            && time <= now
+        {
+            self.enqueue_message(notification);
+            context.stats.pushed_notifications += 1;
+            let interval = context.options.notifications.interval.or_fail()?;
+            self.next_notification_time = Some((time + interval).max(now));
+        }
+        self.send(context.stats).or_fail()?;
+        if paused && !self.is_send_buf_full() {
+            // The readable event may have been consumed while paused, so resume reading here.
+            // The resulting replies are sent by the timer scheduled after this call.
+            self.recv_messages(context).or_fail()?;
+        }
+
+        let writable = !self.send_buf.is_empty();
+        if writable != self.writable {
+            let interest = if writable {
+                mio::Interest::READABLE | mio::Interest::WRITABLE
+            } else {
+                mio::Interest::READABLE
+            };
+            poll.registry()
+                .reregister(&mut self.stream, token, interest)
+                .or_fail()?;
+            self.writable = writable;
+        }
+
+        let closing = self.eof || context.draining;
+        Ok(!(closing && self.pending_replies.is_empty() && self.send_buf.is_empty()))
+    }
+
+    fn is_send_buf_full(&self) -> bool {
+        self.send_buf.len() - self.send_buf_offset >= SEND_BUF_HIGH_WATER_MARK
+    }
+
+    fn recv_messages<H: RequestHandler>(
+        &mut self,
+        context: &mut MessageContext<'_, H>,
+    ) -> orfail::Result<()> {
+        if !self.eof && !context.draining {
+            self.recv(context.stats).or_fail()?;
+            self.handle_messages(context);
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self, stats: &mut ServerStats) -> orfail::Result<()> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).or_fail(),
+                Ok(0) => {
+                    self.eof = true;
+                    if !self.recv_buf.is_empty() && !self.recv_buf.ends_with(b"\n") {
+                        // Treat the trailing bytes without a newline as the last line
+                        self.recv_buf.push(b'\n');
                     }
+                    return Ok(());
+                }
+                Ok(n) => {
+                    self.recv_buf.extend_from_slice(&buf[..n]);
+                    stats.bytes_in += n as u64;
                 }
-                Err(e) => Some(build_error_response(e.to_string())),
-            },
-            Err(e) => Some(build_error_response(e.to_string())),
+            }
+        }
+    }
+
+    fn handle_messages<H: RequestHandler>(&mut self, context: &mut MessageContext<'_, H>) {
+        let mut offset = 0;
+        while let Some(n) = self.recv_buf[offset..].iter().position(|&b| b == b'\n') {
+            let line = &self.recv_buf[offset..offset + n];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            offset += n + 1;
+
+            let reply = handle_message(line, context);
+
+            let now = Instant::now();
+            if context.options.concurrent {
+                // Each request is processed independently,
+                // so replies are sent in the order of completion.
+                let time = now + reply.delay;
+                let i = self.pending_replies.partition_point(|(t, _)| *t <= time);
+                self.pending_replies.insert(i, (time, reply));
+            } else {
+                // Replies are sent in the order of the requests,
+                // so the delay of a reply starts after the preceding reply is sent.
+                let base_time = self
+                    .pending_replies
+                    .back()
+                    .map_or(now, |(t, _)| now.max(*t));
+                self.pending_replies
+                    .push_back((base_time + reply.delay, reply));
+            }
+        }
+        self.recv_buf.drain(..offset);
+    }
+
+    fn enqueue_message(&mut self, message: &str) {
+        self.send_buf.extend_from_slice(message.as_bytes());
+        self.send_buf.push(b'\n');
+    }
+
+    fn send(&mut self, stats: &mut ServerStats) -> orfail::Result<()> {
+        while self.send_buf_offset < self.send_buf.len() {
+            match self.stream.write(&self.send_buf[self.send_buf_offset..]) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).or_fail(),
+                Ok(0) => return Err(orfail::Failure::new("Connection closed by client")),
+                Ok(n) => {
+                    self.send_buf_offset += n;
+                    stats.bytes_out += n as u64;
+                }
+            }
+        }
+
+        if self.send_buf_offset == self.send_buf.len() {
+            self.send_buf.clear();
+            self.send_buf_offset = 0;
+        }
+        Ok(())
+    }
+}
+
+/// What to do in response to an incoming message.
+#[derive(Debug, Default)]
+struct Reply {
+    response: Option<String>,
+    notifications: usize,
+    delay: Duration,
+    close: bool,
+}
+
+impl Reply {
+    fn response(response: String) -> Self {
+        Self {
+            response: Some(response),
+            ..Self::default()
+        }
+    }
+}
+
+fn handle_message<H: RequestHandler>(bytes: &[u8], context: &mut MessageContext<'_, H>) -> Reply {
+    let request_json = std::str::from_utf8(bytes)
+        .map_err(|e| e.to_string())
+        .and_then(|text| nojson::RawJson::parse(text).map_err(|e| e.to_string()));
+    let json = match request_json {
+        Ok(json) => json,
+        Err(e) => {
+            context.stats.errors += 1;
+            return Reply::response(build_error_response(e));
+        }
+    };
+    let value = json.value();
+    if value.kind() != nojson::JsonValueKind::Array {
+        return handle_request(value, context);
+    }
+
+    // Batch request: the responses of the elements are combined into an array
+    let elements: Vec<_> = value.to_array().expect("infallible").collect();
+    if elements.is_empty() {
+        context.stats.errors += 1;
+        return Reply::response(build_error_response("batch must not be empty".to_owned()));
+    }
+    let mut reply = Reply::default();
+    let mut responses = Vec::new();
+    for element in elements {
+        let element_reply = handle_request(element, context);
+        responses.extend(element_reply.response);
+        reply.notifications += element_reply.notifications;
+        reply.delay = reply.delay.max(element_reply.delay);
+        reply.close |= element_reply.close;
+    }
+    if !responses.is_empty() {
+        reply.response = Some(format!("[{}]", responses.join(",")));
+    }
+    reply
+}
+
+fn handle_request<H: RequestHandler>(
+    json_value: nojson::RawJsonValue<'_, '_>,
+    context: &mut MessageContext<'_, H>,
+) -> Reply {
+    let request = match parse_request(json_value) {
+        Ok(request) => request,
+        Err(e) => {
+            context.stats.errors += 1;
+            return Reply::response(build_error_response(e.to_string()));
+        }
+    };
+    let method = &*request.method;
+    context.stats.increment_method_count(method);
+    let Some(request_id) = request.id else {
+        context.stats.notifications += 1;
+        return Reply::default();
+    };
+    context.stats.requests += 1;
+
+    let options = context.options;
+    let faults = &options.faults;
+    let rng = &mut *context.rng;
+    if faults.close_rate(method).is_some_and(|p| rng.gen_bool(p)) {
+        return Reply {
+            close: true,
+            ..Reply::default()
         };
+    }
 
-        if let Some(response) = response {
-            let _ = socket.send_to(response.as_bytes(), peer_addr);
-        }
-    }
-}
-
-fn handle_client(stream: TcpStream) -> orfail::Result<()> {
-    let reader = BufReader::new(stream.try_clone().or_fail()?);
-    let mut writer = BufWriter::new(stream);
-    for line in reader.lines() {
-        let line = line.or_fail()?;
-        nojson::RawJson::parse(&line)
-            .and_then(|json| {
-                let json_value = json.value();
-                let Some(request_id) = parse_request(json_value)? else {
-                    return Ok(Ok(()));
-                };
-                let response = nojson::object(|f| {
-                    f.member("jsonrpc", "2.0")?;
-                    f.member("id", request_id)?;
-                    f.member("result", json_value)
-                });
-                Ok(writeln!(writer, "{response}"))
-            })
-            .unwrap_or_else(|e| {
-                let response = build_error_response(e.to_string());
-                writeln!(writer, "{response}")
-            })
-            .or_fail()?;
-        writer.flush().or_fail()?;
+    let delay = faults
+        .delay(method)
+        .map(|d| d.sample(rng))
+        .unwrap_or_default();
+    let mut notifications = 0;
+    let response = if faults.drop_rate(method).is_some_and(|p| rng.gen_bool(p)) {
+        None
+    } else if faults.error_rate(method).is_some_and(|p| rng.gen_bool(p)) {
+        context.stats.errors += 1;
+        Some(build_injected_error_response(request_id))
+    } else {
+        if options.notifications.subscribe_method.as_deref() == Some(method) {
+            notifications = options.notifications.subscribe_count;
+        }
+        Some(context.handler.handle_request(json_value, request_id))
+    };
+    Reply {
+        response,
+        notifications,
+        delay,
+        close: false,
+    }
+}
+
+/// Notifications pushed from the server without corresponding requests.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct PushNotifications {
+    pub notification: String,
+    pub interval: Option<Duration>,
+    pub subscribe_method: Option<String>,
+    pub subscribe_count: usize,
+}
+
+/// Faults to be injected into the responses for testing clients.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct FaultInjection {
+    pub delays: Vec<MethodScoped<Delay>>,
+    pub error_rates: Vec<MethodScoped<Probability>>,
+    pub drop_rates: Vec<MethodScoped<Probability>>,
+    pub close_rates: Vec<MethodScoped<Probability>>,
+}
+
+impl FaultInjection {
+    fn delay(&self, method: &str) -> Option<&Delay> {
+        MethodScoped::lookup(&self.delays, method)
+    }
+
+    fn error_rate(&self, method: &str) -> Option<f64> {
+        MethodScoped::lookup(&self.error_rates, method).map(|p| p.0)
+    }
+
+    fn drop_rate(&self, method: &str) -> Option<f64> {
+        MethodScoped::lookup(&self.drop_rates, method).map(|p| p.0)
+    }
+
+    fn close_rate(&self, method: &str) -> Option<f64> {
+        MethodScoped::lookup(&self.close_rates, method).map(|p| p.0)
     }
-    Ok(())
+}
+
+/// A value that is optionally restricted to requests of a specific method (`[METHOD=]VALUE`).
+#[derive(Debug, Clone)]
+pub(crate) struct MethodScoped<T> {
+    pub method: Option<String>,
+    pub value: T,
+}
+
+impl<T> MethodScoped<T> {
+    /// Returns the value for the method, preferring method-specific values over unscoped ones.
+    fn lookup<'a>(items: &'a [Self], method: &str) -> Option<&'a T> {
+        items
+            .iter()
+            .rev()
+            .find(|x| x.method.as_deref() == Some(method))
+            .or_else(|| items.iter().rev().find(|x| x.method.is_none()))
+            .map(|x| &x.value)
+    }
+}
+
+impl<T: FromStr<Err = String>> FromStr for MethodScoped<T> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((method, value)) => Ok(Self {
+                method: Some(method.to_owned()),
+                value: value.parse()?,
+            }),
+            None => Ok(Self {
+                method: None,
+                value: s.parse()?,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Delay {
+    Fixed(Duration),
+    Uniform { min: Duration, max: Duration },
+    Exponential { mean: Duration },
+}
+
+impl Delay {
+    fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Delay::Fixed(d) => d,
+            Delay::Uniform { min, max } => rng.gen_duration(min, max),
+            Delay::Exponential { mean } => rng.gen_exponential(mean),
+        }
+    }
+}
+
+impl FromStr for Delay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split(':').collect::<Vec<_>>()[..] {
+            [secs] => Ok(Self::Fixed(parse_seconds(secs)?)),
+            ["uniform", min, max] => {
+                let (min, max) = (parse_seconds(min)?, parse_seconds(max)?);
+                if min > max {
+                    return Err(format!("min delay must not exceed max delay: {s:?}"));
+                }
+                Ok(Self::Uniform { min, max })
+            }
+            ["exp", mean] => Ok(Self::Exponential {
+                mean: parse_seconds(mean)?,
+            }),
+            _ => Err(format!(
+                "delay must be SECONDS, uniform:MIN_SECONDS:MAX_SECONDS or exp:MEAN_SECONDS: {s:?}"
+            )),
+        }
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid seconds: {s:?}"))
+}
+
+#[derive(Debug)]
+struct ParsedRequest<'text, 'raw> {
+    id: Option<nojson::RawJsonValue<'text, 'raw>>,
+    method: Cow<'text, str>,
 }
 
 fn parse_request<'text, 'raw>(
     value: nojson::RawJsonValue<'text, 'raw>,
-) -> Result<Option<nojson::RawJsonValue<'text, 'raw>>, nojson::JsonParseError> {
+) -> Result<ParsedRequest<'text, 'raw>, nojson::JsonParseError> {
     if value.kind() == nojson::JsonValueKind::Array {
-        return Err(value.invalid("batch requests are not supported"));
+        return Err(value.invalid("nested batch requests are not allowed"));
     }
 
     let mut has_jsonrpc = false;
-    let mut has_method = false;
+    let mut method = None;
     let mut id = None;
     for (name, value) in value.to_object()? {
         match name.as_string_str()? {
@@ -151,7 +1080,7 @@ fn parse_request<'text, 'raw>(
                 if value.kind() != nojson::JsonValueKind::String {
                     return Err(value.invalid("method must be a string"));
                 }
-                has_method = true;
+                method = Some(value.to_unquoted_string_str()?);
             }
             "params"
                 if !matches!(
@@ -170,11 +1099,26 @@ fn parse_request<'text, 'raw>(
     if !has_jsonrpc {
         return Err(value.invalid("jsonrpc field is required"));
     }
-    if !has_method {
+    let Some(method) = method else {
         return Err(value.invalid("method field is required"));
-    }
+    };
+
+    Ok(ParsedRequest { id, method })
+}
 
-    Ok(id)
+fn build_injected_error_response(request_id: nojson::RawJsonValue<'_, '_>) -> String {
+    let response = nojson::object(|f| {
+        f.member("jsonrpc", "2.0")?;
+        f.member(
+            "error",
+            nojson::object(|f| {
+                f.member("code", -32000)?; // server-error code
+                f.member("message", "Injected error")
+            }),
+        )?;
+        f.member("id", request_id)
+    });
+    response.to_string()
 }
 
 fn build_error_response(message: String) -> String {
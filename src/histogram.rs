@@ -0,0 +1,244 @@
+/// Histogram with log-linear buckets (similar to HdrHistogram).
+///
+/// Values below `2^precision_bits` have their own buckets.
+/// Above that, each power-of-two range is divided into `2^precision_bits` buckets of equal width,
+/// so the width of a bucket never exceeds `1 / 2^precision_bits` of its lower bound.
+///
+/// Memory usage depends only on the magnitude of the largest recorded value, not on the number of values.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    precision_bits: u32,
+    counts: Vec<u64>,
+    count: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// Default precision, which bounds the relative error of [`Histogram::value_at_rank()`] to `1 / 256`.
+    pub const DEFAULT_PRECISION_BITS: u32 = 7;
+
+    pub fn new(precision_bits: u32) -> Self {
+        Self {
+            precision_bits,
+            counts: Vec::new(),
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.record_n(value, 1);
+    }
+
+    pub fn record_n(&mut self, value: u64, n: u64) {
+        if n == 0 {
+            return;
+        }
+
+        let i = self.bucket_index(value);
+        if self.counts.len() <= i {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += n;
+        self.count += n;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Returns the count of each bucket.
+    ///
+    /// Histograms with the same precision share the same bucket layout, so their counts can be compared by index.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Estimates the value at the given zero-based rank in the sorted recorded values.
+    ///
+    /// The estimate is the midpoint of the bucket containing the rank, clamped to the recorded min and max
+    /// (the first and last ranks are exactly the min and max).
+    /// Therefore, its error is at most half of the bucket width.
+    pub fn value_at_rank(&self, rank: u64) -> Option<f64> {
+        if rank == 0 {
+            return self.min().map(|v| v as f64);
+        }
+        if rank + 1 == self.count {
+            return self.max().map(|v| v as f64);
+        }
+
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if rank < seen {
+                let (lower, width) = self.bucket_range(i);
+                let midpoint = lower as f64 + (width - 1) as f64 / 2.0;
+                return Some(midpoint.clamp(self.min as f64, self.max as f64));
+            }
+        }
+        None
+    }
+
+    /// Estimates a percentile (0 to 100) by linear interpolation between the closest ranks.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        let rank = percentile / 100.0 * self.count.checked_sub(1)? as f64;
+        let lower = self.value_at_rank(rank.floor() as u64)?;
+        let upper = self.value_at_rank(rank.ceil() as u64)?;
+        Some(lower + (upper - lower) * rank.fract())
+    }
+
+    /// Returns a copy of this histogram with fewer (wider) buckets.
+    pub fn coarsen(&self, precision_bits: u32) -> Self {
+        let mut histogram = Self::new(precision_bits.min(self.precision_bits));
+        for (i, &count) in self.counts.iter().enumerate() {
+            let (lower, _) = self.bucket_range(i);
+            histogram.record_n(lower, count);
+        }
+        histogram.min = self.min;
+        histogram.max = self.max;
+        histogram
+    }
+
+    /// Returns `(lower, upper, count)` of each bucket between the first and last non-empty buckets.
+    ///
+    /// The upper bound is exclusive.
+    pub fn buckets(&self) -> impl '_ + Iterator<Item = (u64, u64, u64)> {
+        let start = self.counts.iter().position(|&c| c > 0).unwrap_or(0);
+        self.counts
+            .iter()
+            .enumerate()
+            .skip(start)
+            .map(|(i, &count)| {
+                let (lower, width) = self.bucket_range(i);
+                (lower, lower.saturating_add(width), count)
+            })
+    }
+
+    fn bucket_index(&self, value: u64) -> usize {
+        let sub_buckets = 1u64 << self.precision_bits;
+        if value < sub_buckets {
+            return value as usize;
+        }
+
+        let msb = u64::BITS - 1 - value.leading_zeros();
+        let exponent = msb - self.precision_bits;
+        let sub = (value >> exponent) & (sub_buckets - 1);
+        (sub_buckets + u64::from(exponent) * sub_buckets + sub) as usize
+    }
+
+    /// Returns the lower bound and width of the bucket.
+    fn bucket_range(&self, index: usize) -> (u64, u64) {
+        let sub_buckets = 1u64 << self.precision_bits;
+        let index = index as u64;
+        if index < sub_buckets {
+            return (index, 1);
+        }
+
+        let exponent = (index - sub_buckets) / sub_buckets;
+        let sub = (index - sub_buckets) % sub_buckets;
+        ((sub_buckets + sub) << exponent, 1 << exponent)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_PRECISION_BITS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_contains_value() {
+        let histogram = Histogram::default();
+        let values = (0..100_000).chain((0..64).flat_map(|shift| {
+            let v = 1u64 << shift;
+            [v - 1, v, v + 1, v.saturating_mul(3) / 2]
+        }));
+        for value in values.chain([u64::MAX]) {
+            let (lower, width) = histogram.bucket_range(histogram.bucket_index(value));
+            assert!(lower <= value, "value={value}, lower={lower}");
+            assert!(
+                value - lower < width,
+                "value={value}, lower={lower}, width={width}"
+            );
+            assert!(width <= (lower >> Histogram::DEFAULT_PRECISION_BITS).max(1));
+        }
+    }
+
+    #[test]
+    fn value_at_rank_error_bound() {
+        let mut histogram = Histogram::default();
+        let values = (1..=100_000u64)
+            .map(|i| i * i % 1_000_003 + 1)
+            .collect::<Vec<_>>();
+        for &value in &values {
+            histogram.record(value);
+        }
+
+        let mut sorted = values.clone();
+        sorted.sort();
+        for (rank, &value) in sorted.iter().enumerate() {
+            let estimate = histogram.value_at_rank(rank as u64).expect("in range");
+            let error = (estimate - value as f64).abs() / value as f64;
+            assert!(
+                error <= 1.0 / 256.0,
+                "rank={rank}, value={value}, estimate={estimate}"
+            );
+        }
+        assert_eq!(histogram.value_at_rank(values.len() as u64), None);
+    }
+
+    #[test]
+    fn exact_min_and_max() {
+        let mut histogram = Histogram::default();
+        for value in [1_000, 123_456, 99_999_999] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.value_at_rank(0), Some(1_000.0));
+        assert_eq!(histogram.value_at_rank(2), Some(99_999_999.0));
+        assert_eq!(histogram.percentile(0.0), Some(1_000.0));
+        assert_eq!(histogram.percentile(100.0), Some(99_999_999.0));
+    }
+
+    #[test]
+    fn percentile_interpolation() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), None);
+
+        // Values below 2^precision_bits are recorded exactly
+        for value in 0..=100 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.percentile(50.0), Some(50.0));
+        assert_eq!(histogram.percentile(12.5), Some(12.5));
+        assert_eq!(histogram.percentile(99.0), Some(99.0));
+    }
+
+    #[test]
+    fn coarsen() {
+        let mut histogram = Histogram::default();
+        for value in 0..10_000 {
+            histogram.record(value);
+        }
+        let coarse = histogram.coarsen(2);
+        assert_eq!(coarse.count(), histogram.count());
+        assert_eq!(coarse.min(), Some(0));
+        assert_eq!(coarse.max(), Some(9_999));
+        assert!(coarse.counts().len() < histogram.counts().len());
+    }
+}
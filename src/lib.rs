@@ -1,7 +1,12 @@
 pub mod bench;
 pub mod call;
 pub mod echo_server;
+pub mod mock_server;
 pub mod req;
 pub mod stats;
 
+mod histogram;
+mod rng;
+mod table;
+mod template;
 mod types;
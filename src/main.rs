@@ -13,7 +13,8 @@ fn main() -> noargs::Result<()> {
         || jlot::call::try_run(&mut args)?
         || jlot::bench::try_run(&mut args)?
         || jlot::stats::try_run(&mut args)?
-        || jlot::echo_server::try_run(&mut args)?;
+        || jlot::echo_server::try_run(&mut args)?
+        || jlot::mock_server::try_run(&mut args)?;
 
     if let Some(help) = args.finish()? {
         print!("{help}");
@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use orfail::OrFail;
+
+use crate::echo_server::{RequestHandler, ServerOptions, run_server_tcp, run_server_udp};
+use crate::template::{TextPart, split_placeholders};
+use crate::types::{JsonPointer, ServerAddr};
+
+pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
+    if !noargs::cmd("mock-server")
+        .doc(concat!(
+            "Run a JSON-RPC mock server that responds according to a rules file\n",
+            "\n",
+            "Each line of the rules file is a JSON object describing a rule.\n",
+            "Rules are evaluated in order and the first matching rule is used:\n",
+            "\n",
+            "  {\"method\": \"add\", \"params\": {\"x\": 1}, \"result\": 3}\n",
+            "  {\"method\": \"hello\", \"result_template\": {\"message\": \"Hi, {{/params/0}}\"}}\n",
+            "  {\"method\": \"fail\", \"error\": {\"code\": -32000, \"message\": \"Oops\"}}\n",
+            "  {\"result\": null}\n",
+            "\n",
+            "- \"method\" (optional): Method name to match (omit to match any method)\n",
+            "- \"params\" (optional): Pattern that the request params must contain\n",
+            "- \"result\": Fixed result value\n",
+            "- \"result_template\": Result value in which \"{{<JSON_POINTER>}}\" placeholders\n",
+            "  are replaced with the values in the request object (\"{{{{\" is a literal \"{{\")\n",
+            "- \"error\": JSON-RPC error object\n",
+            "\n",
+            "If no rule matches, a \"Method not found\" (-32601) error is returned.\n",
+            "\n",
+            "On SIGINT or SIGTERM, the server finishes in-flight requests and prints\n",
+            "the statistics of the handled messages to the standard error."
+        ))
+        .take(args)
+        .is_present()
+    {
+        return Ok(false);
+    }
+
+    let use_udp: bool = noargs::flag("udp")
+        .short('u')
+        .doc("Use UDP instead of TCP (one packet per request/response)")
+        .take(args)
+        .is_present();
+    let rules_path: PathBuf = noargs::opt("rules")
+        .short('r')
+        .ty("PATH")
+        .doc("Path to the JSON Lines file containing the rules")
+        .example("rules.jsonl")
+        .take(args)
+        .then(|o| o.value().parse())?;
+    let listen_addr: ServerAddr = noargs::arg("<ADDR>")
+        .doc("Listen address")
+        .example("127.0.0.1:8080")
+        .take(args)
+        .then(|a| a.value().parse())?;
+
+    if args.metadata().help_mode {
+        return Ok(true);
+    }
+
+    let handler = Arc::new(MockHandler::load(&rules_path).or_fail()?);
+    if use_udp {
+        run_server_udp(listen_addr, handler, ServerOptions::default())?;
+    } else {
+        run_server_tcp(listen_addr, handler, ServerOptions::default())?;
+    }
+    Ok(true)
+}
+
+#[derive(Debug)]
+struct MockHandler {
+    rules: Vec<Rule>,
+}
+
+impl MockHandler {
+    fn load(path: &PathBuf) -> orfail::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .or_fail_with(|e| format!("Failed to read '{}': {e}", path.display()))?;
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let rule = Rule::parse(line).or_fail_with(|e| {
+                format!(
+                    "Invalid rule at line {} of '{}': {e}",
+                    i + 1,
+                    path.display()
+                )
+            })?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+}
+
+impl RequestHandler for MockHandler {
+    fn handle_request(
+        &self,
+        request: nojson::RawJsonValue<'_, '_>,
+        request_id: nojson::RawJsonValue<'_, '_>,
+    ) -> String {
+        let rule = self.rules.iter().find(|rule| rule.matches(request));
+        let response = nojson::object(|f| {
+            f.member("jsonrpc", "2.0")?;
+            match rule.map(|rule| &rule.action) {
+                Some(RuleAction::Result(result)) => f.member("result", result)?,
+                Some(RuleAction::ResultTemplate(template)) => f.member(
+                    "result",
+                    Template {
+                        template: template.value(),
+                        request,
+                    },
+                )?,
+                Some(RuleAction::Error(error)) => f.member("error", error)?,
+                None => f.member(
+                    "error",
+                    nojson::object(|f| {
+                        f.member("code", -32601)?; // method-not-found code
+                        f.member("message", "Method not found")
+                    }),
+                )?,
+            }
+            f.member("id", request_id)
+        });
+        response.to_string()
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    method: Option<String>,
+    params: Option<nojson::RawJsonOwned>,
+    action: RuleAction,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Self, nojson::JsonParseError> {
+        let json = nojson::RawJson::parse(line)?;
+        let value = json.value();
+
+        let mut method = None;
+        let mut params = None;
+        let mut action = None;
+        for (name, member) in value.to_object()? {
+            let name = name.to_unquoted_string_str()?;
+            let new_action = match name.as_ref() {
+                "method" => {
+                    method = Some(member.try_into()?);
+                    continue;
+                }
+                "params" => {
+                    params = Some(member.extract().into_owned());
+                    continue;
+                }
+                "result" => RuleAction::Result(member.extract().into_owned()),
+                "result_template" => {
+                    validate_template(member)?;
+                    RuleAction::ResultTemplate(member.extract().into_owned())
+                }
+                "error" => {
+                    validate_error_object(member)?;
+                    RuleAction::Error(member.extract().into_owned())
+                }
+                _ => return Err(member.invalid(format!("unknown rule member: {name:?}"))),
+            };
+            if action.is_some() {
+                return Err(member.invalid(
+                    "only one of \"result\", \"result_template\" or \"error\" can be specified",
+                ));
+            }
+            action = Some(new_action);
+        }
+
+        let action = action.ok_or_else(|| {
+            value.invalid("one of \"result\", \"result_template\" or \"error\" is required")
+        })?;
+        Ok(Self {
+            method,
+            params,
+            action,
+        })
+    }
+
+    fn matches(&self, request: nojson::RawJsonValue<'_, '_>) -> bool {
+        if let Some(method) = &self.method {
+            let Some(request_method) = request
+                .to_member("method")
+                .ok()
+                .and_then(|m| m.get())
+                .and_then(|m| m.to_unquoted_string_str().ok())
+            else {
+                return false;
+            };
+            if request_method != method.as_str() {
+                return false;
+            }
+        }
+
+        if let Some(params) = &self.params {
+            let Some(request_params) = request.to_member("params").ok().and_then(|m| m.get())
+            else {
+                return false;
+            };
+            if !json_matches(params.value(), request_params) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Debug)]
+enum RuleAction {
+    Result(nojson::RawJsonOwned),
+    ResultTemplate(nojson::RawJsonOwned),
+    Error(nojson::RawJsonOwned),
+}
+
+fn validate_error_object(
+    value: nojson::RawJsonValue<'_, '_>,
+) -> Result<(), nojson::JsonParseError> {
+    let _: i64 = value.to_member("code")?.required()?.try_into()?;
+    let _: String = value.to_member("message")?.required()?.try_into()?;
+    Ok(())
+}
+
+fn validate_template(value: nojson::RawJsonValue<'_, '_>) -> Result<(), nojson::JsonParseError> {
+    match value.kind() {
+        nojson::JsonValueKind::String => {
+            let text = value.to_unquoted_string_str()?;
+            for part in split_placeholders(&text).map_err(|e| value.invalid(e))? {
+                if let TextPart::Placeholder(pointer) = part {
+                    pointer
+                        .trim()
+                        .parse::<JsonPointer>()
+                        .map_err(|e| value.invalid(e))?;
+                }
+            }
+        }
+        nojson::JsonValueKind::Array => {
+            for element in value.to_array()? {
+                validate_template(element)?;
+            }
+        }
+        nojson::JsonValueKind::Object => {
+            for (_, member) in value.to_object()? {
+                validate_template(member)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Returns `true` if `value` contains all the members / elements specified in `pattern`.
+fn json_matches(
+    pattern: nojson::RawJsonValue<'_, '_>,
+    value: nojson::RawJsonValue<'_, '_>,
+) -> bool {
+    use nojson::JsonValueKind::*;
+
+    match (pattern.kind(), value.kind()) {
+        (Object, Object) => pattern.to_object().expect("infallible").all(|(name, p)| {
+            let name = name.to_unquoted_string_str().expect("infallible");
+            value
+                .to_member(&name)
+                .ok()
+                .and_then(|m| m.get())
+                .is_some_and(|v| json_matches(p, v))
+        }),
+        (Array, Array) => {
+            let patterns = pattern.to_array().expect("infallible").collect::<Vec<_>>();
+            let values = value.to_array().expect("infallible").collect::<Vec<_>>();
+            patterns.len() == values.len()
+                && patterns
+                    .into_iter()
+                    .zip(values)
+                    .all(|(p, v)| json_matches(p, v))
+        }
+        (String, String) => {
+            pattern.to_unquoted_string_str().ok() == value.to_unquoted_string_str().ok()
+        }
+        (Integer | Float, Integer | Float) => {
+            let p: Option<f64> = pattern.try_into().ok();
+            let v: Option<f64> = value.try_into().ok();
+            p.is_some() && p == v
+        }
+        (p, v) => p == v && pattern.as_raw_str() == value.as_raw_str(),
+    }
+}
+
+struct Template<'a, 'text, 'raw> {
+    template: nojson::RawJsonValue<'a, 'a>,
+    request: nojson::RawJsonValue<'text, 'raw>,
+}
+
+impl nojson::DisplayJson for Template<'_, '_, '_> {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        match self.template.kind() {
+            nojson::JsonValueKind::String => {
+                let text = self.template.to_unquoted_string_str().expect("infallible");
+                let parts = split_placeholders(&text).expect("validated by validate_template()");
+                if let [TextPart::Placeholder(pointer)] = parts[..] {
+                    // The whole string is a placeholder: embed the referenced JSON value as is
+                    return f.value(self.lookup(pointer));
+                }
+
+                let mut expanded = String::new();
+                for part in parts {
+                    match part {
+                        TextPart::Text(s) => expanded.push_str(s),
+                        TextPart::Placeholder(pointer) => match self.lookup(pointer) {
+                            Some(v) if v.kind() == nojson::JsonValueKind::String => {
+                                expanded.push_str(&v.to_unquoted_string_str().expect("infallible"))
+                            }
+                            Some(v) => expanded.push_str(&v.to_string()),
+                            None => {}
+                        },
+                    }
+                }
+                f.string(expanded)
+            }
+            nojson::JsonValueKind::Array => f.array(|f| {
+                f.elements(
+                    self.template
+                        .to_array()
+                        .expect("infallible")
+                        .map(|template| Template {
+                            template,
+                            request: self.request,
+                        }),
+                )
+            }),
+            nojson::JsonValueKind::Object => {
+                f.object(|f| {
+                    f.members(self.template.to_object().expect("infallible").map(
+                        |(name, template)| {
+                            (
+                                name.to_unquoted_string_str().expect("infallible"),
+                                Template {
+                                    template,
+                                    request: self.request,
+                                },
+                            )
+                        },
+                    ))
+                })
+            }
+            _ => f.value(self.template),
+        }
+    }
+}
+
+impl<'text, 'raw> Template<'_, 'text, 'raw> {
+    fn lookup(&self, pointer: &str) -> Option<nojson::RawJsonValue<'text, 'raw>> {
+        pointer
+            .trim()
+            .parse::<JsonPointer>()
+            .ok()?
+            .get(self.request)
+    }
+}
@@ -1,4 +1,13 @@
 use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use orfail::OrFail;
+
+use crate::{
+    rng::Rng,
+    template::{Context, Template, Value, gen_uuid},
+    types::{Probability, RequestId},
+};
 
 pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
     if !noargs::cmd("req")
@@ -14,53 +23,692 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
         .doc("Exclude the \"id\" field from the resulting JSON object")
         .take(args)
         .is_present();
-    let count: NonZeroUsize = noargs::opt("count")
+    let notification_rate: Option<Probability> = noargs::opt("notification-rate")
+        .ty("PROBABILITY")
+        .doc("Probability of generating each request as a notification (without the \"id\" field)")
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let batch_size: Option<NonZeroUsize> = noargs::opt("batch-size")
+        .ty("INTEGER")
+        .doc(concat!(
+            "Group the generated requests into JSON-RPC batch arrays of the given size\n",
+            "\n",
+            "Each batch is output as a line (the last batch may be smaller).\n",
+            "--count is the number of requests, not batches."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let id_start: Option<i64> = noargs::opt("id-start")
+        .ty("INTEGER")
+        .doc("ID of the first request (subsequent requests have consecutive IDs; default: 0)")
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let id_prefix: Option<String> = noargs::opt("id-prefix")
+        .ty("PREFIX")
+        .doc(concat!(
+            "Use string IDs starting with PREFIX (e.g., `--id-prefix c1-` generates \"c1-0\", \"c1-1\", ...)\n",
+            "\n",
+            "This is useful to merge independently generated requests without ID collisions."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let id_uuid: bool = noargs::flag("id-uuid")
+        .doc("Use random UUID strings (following --id-prefix if specified) as IDs")
+        .take(args)
+        .is_present();
+    let count: Option<NonZeroUsize> = noargs::opt("count")
         .short('c')
         .ty("INTEGER")
-        .doc("Count of requests to generate")
-        .default("1")
+        .doc(concat!(
+            "Count of requests to generate\n",
+            "(default: 1, or the number of records in --params-file or --data-file)"
+        ))
         .take(args)
-        .then(|o| o.value().parse())?;
-    let params: Option<nojson::RawJsonOwned> = noargs::opt("params")
+        .present_and_then(|o| o.value().parse())?;
+    let params: Option<Template> = noargs::opt("params")
         .short('p')
         .ty("OBJECT | ARRAY")
-        .doc("Request parameters (JSON array or JSON object)")
+        .doc(concat!(
+            "Request parameters (JSON array or JSON object)\n",
+            "\n",
+            "Strings can contain placeholders expanded for each request:\n",
+            "- {{id}}: Request ID\n",
+            "- {{seq}}: Zero-based sequence number of the request\n",
+            "- {{rand_int MIN MAX}}: Random integer in the range [MIN, MAX]\n",
+            "- {{rand_str LEN}}: Random alphanumeric string of the length\n",
+            "- {{uuid}}: Random UUID (version 4)\n",
+            "- {{choice VALUE...}}: Randomly chosen JSON value\n",
+            "- {{now_micros}}: Current Unix timestamp in microseconds\n",
+            "- {{column NAME}}: Value of the column in the current record of --data-file\n",
+            "\n",
+            "The following placeholders generate integer keys in the range [MIN, MAX]\n",
+            "to simulate skewed access patterns (e.g., of caches):\n",
+            "- {{zipf MIN MAX SKEW}}: Zipfian distribution where MIN is the hottest key\n",
+            "  (SKEW is the exponent; 0 is uniform and larger values are more skewed)\n",
+            "- {{hotspot MIN MAX HOT_KEYS HOT_TRAFFIC}}: HOT_TRAFFIC fraction of the requests\n",
+            "  access the first HOT_KEYS fraction of the keys (e.g., 0.2 0.8 for 80/20)\n",
+            "- {{sequential MIN MAX}}: MIN, MIN+1, ..., MAX, MIN, ... (e.g., for inserts)\n",
+            "- {{latest MIN MAX SKEW}}: Zipfian distribution where the most recent key of\n",
+            "  {{sequential}} (or MAX if none) is the hottest (e.g., for reads after inserts)\n",
+            "\n",
+            "If a whole string is a placeholder, it is replaced with the JSON value\n",
+            "(e.g., \"{{rand_int 1 10}}\" becomes a number).\n",
+            "Use \"{{{{\" to write a literal \"{{\" (e.g., \"{{{{name}}\" becomes \"{{name}}\")."
+        ))
         .take(args)
         .present_and_then(|a| {
             let json = nojson::RawJson::parse(a.value())?;
-            if !matches!(
-                json.value().kind(),
-                nojson::JsonValueKind::Array | nojson::JsonValueKind::Object
-            ) {
-                return Err(json.value().invalid("must be a JSON array or JSON object"));
-            }
-            Ok(json.into_owned())
+            parse_params(json.value())
         })?;
-    let method: String = noargs::arg("<METHOD>")
-        .doc("Method name")
+    let mut mix = Vec::new();
+    while let Some(entry) = noargs::opt("mix")
+        .short('m')
+        .ty("METHOD:WEIGHT[=PARAMS]")
+        .doc(concat!(
+            "Add a method to the mix of generated requests\n",
+            "\n",
+            "Each request is generated from an entry of the mix chosen according to the weights\n",
+            "(e.g., `--mix get:70 --mix put:25='{\"key\":\"{{rand_int 1 100}}\"}' --mix delete:5`).\n",
+            "PARAMS accepts the same placeholders as --params.\n",
+            "This option can be specified multiple times."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse::<MixEntry>())?
+    {
+        mix.push(entry);
+    }
+    let mix_file: Option<PathBuf> = noargs::opt("mix-file")
+        .ty("PATH")
+        .doc(concat!(
+            "Path to a JSON Lines file containing the mix of generated requests\n",
+            "\n",
+            "Each line is a JSON object describing an entry of the mix:\n",
+            "\n",
+            "  {\"method\": \"get\", \"params\": {\"key\": \"{{rand_int 1 100}}\"}, \"weight\": 70}\n",
+            "\n",
+            "- \"method\": Method name\n",
+            "- \"params\" (optional): Request parameters (same as --params)\n",
+            "- \"weight\" (optional): Relative frequency of the entry (default: 1)"
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let params_file: Option<PathBuf> = noargs::opt("params-file")
+        .ty("PATH")
+        .doc(concat!(
+            "Path to a JSON Lines file containing the params of each request\n",
+            "\n",
+            "Each line is a JSON array or JSON object used as is (without placeholders).\n",
+            "Requests are generated until the end of the file unless --cycle is specified."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let data_file: Option<PathBuf> = noargs::opt("data-file")
+        .ty("PATH")
+        .doc(concat!(
+            "Path to a CSV file containing a record for each request\n",
+            "\n",
+            "The first line is the header containing the column names.\n",
+            "Column values (as strings) are referred to by {{column NAME}} placeholders\n",
+            "in the params. If no params are given, the params are an object of\n",
+            "all columns of the record.\n",
+            "Requests are generated until the end of the file unless --cycle is specified."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let cycle: bool = noargs::flag("cycle")
+        .doc(concat!(
+            "Restart from the first record of --params-file or --data-file\n",
+            "after the last one until --count requests are generated"
+        ))
+        .take(args)
+        .is_present();
+    let interleave: bool = noargs::flag("interleave")
+        .doc(concat!(
+            "Interleave the mix entries in exact proportion to the weights\n",
+            "instead of choosing them randomly"
+        ))
+        .take(args)
+        .is_present();
+    let seed: Option<u64> = noargs::opt("seed")
+        .ty("INTEGER")
+        .doc(concat!(
+            "Seed for the random placeholders and the mix selection\n",
+            "(defaults to the current time)"
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let method: Option<String> = noargs::arg("[METHOD]")
+        .doc("Method name (required unless --mix or --mix-file is specified)")
         .example("GetFoo")
         .take(args)
-        .then(|a| a.value().parse())?;
+        .present_and_then(|a| a.value().parse())?;
 
     if args.metadata().help_mode {
         return Ok(true);
     }
 
-    // Generate and output requests
-    for id in 0..count.get() {
-        let json = nojson::object(|f| {
-            f.member("jsonrpc", "2.0")?;
-            f.member("method", &method)?;
-            if let Some(params) = &params {
-                f.member("params", params)?;
-            }
-            if !notification {
-                f.member("id", id)?;
-            }
-            Ok(())
+    if let Some(path) = &mix_file {
+        mix.extend(load_mix_file(path)?);
+    }
+    if let Some(method) = method {
+        mix.is_empty()
+            .or_fail_with(|()| "METHOD cannot be combined with --mix or --mix-file".to_owned())?;
+        mix.push(MixEntry {
+            method,
+            params,
+            weight: 1.0,
         });
-        println!("{json}");
+    } else {
+        (!mix.is_empty())
+            .or_fail_with(|()| "METHOD, --mix or --mix-file is required".to_owned())?;
+        params
+            .is_none()
+            .or_fail_with(|()| "--params cannot be combined with --mix or --mix-file".to_owned())?;
     }
 
+    (params_file.is_none() || data_file.is_none())
+        .or_fail_with(|()| "--params-file cannot be combined with --data-file".to_owned())?;
+    let data = match (&params_file, &data_file) {
+        (Some(path), _) => {
+            mix.iter().all(|e| e.params.is_none()).or_fail_with(|()| {
+                "--params-file cannot be combined with params in --params or the mix".to_owned()
+            })?;
+            Some(DataSet::load_params_file(path)?)
+        }
+        (_, Some(path)) => Some(DataSet::load_csv_file(path)?),
+        (None, None) => {
+            (!cycle)
+                .or_fail_with(|()| "--cycle requires --params-file or --data-file".to_owned())?;
+            None
+        }
+    };
+    let columns = match &data {
+        Some(DataSet::Rows { columns, .. }) => columns.as_slice(),
+        _ => &[],
+    };
+    for name in mix
+        .iter()
+        .filter_map(|e| e.params.as_ref())
+        .flat_map(|t| t.columns())
+    {
+        columns.iter().any(|c| c == name).or_fail_with(|()| {
+            if data_file.is_some() {
+                format!("unknown column in {{{{column}}}} placeholder: {name}")
+            } else {
+                "{{column}} placeholders require --data-file".to_owned()
+            }
+        })?;
+    }
+    let count = match (&data, count) {
+        (None, count) => count.map_or(1, NonZeroUsize::get),
+        (Some(data), None) => data.len(),
+        (Some(_), Some(count)) if cycle => count.get(),
+        (Some(data), Some(count)) => count.get().min(data.len()),
+    };
+
+    (!(notification && notification_rate.is_some())).or_fail_with(|()| {
+        "--notification cannot be combined with --notification-rate".to_owned()
+    })?;
+    let ids = if notification {
+        (id_start.is_none() && id_prefix.is_none() && !id_uuid).or_fail_with(|()| {
+            "--notification cannot be combined with --id-start, --id-prefix or --id-uuid".to_owned()
+        })?;
+        None
+    } else if id_uuid {
+        id_start
+            .is_none()
+            .or_fail_with(|()| "--id-start cannot be combined with --id-uuid".to_owned())?;
+        Some(IdGenerator::Uuid {
+            prefix: id_prefix.unwrap_or_default(),
+        })
+    } else {
+        let start = id_start.unwrap_or(0);
+        start
+            .checked_add(count as i64 - 1)
+            .or_fail_with(|()| "--id-start is too large for --count".to_owned())?;
+        Some(IdGenerator::Sequential {
+            start,
+            prefix: id_prefix,
+        })
+    };
+
+    let command = ReqCommand {
+        mix,
+        interleave,
+        ids,
+        notification_rate: notification_rate.map(|p| p.0),
+        batch_size: batch_size.map_or(1, NonZeroUsize::get),
+        data,
+        count,
+        rng: seed.map_or_else(Rng::from_time, Rng::new),
+    };
+    command.run();
     Ok(true)
 }
+
+fn parse_params(value: nojson::RawJsonValue<'_, '_>) -> Result<Template, nojson::JsonParseError> {
+    if !matches!(
+        value.kind(),
+        nojson::JsonValueKind::Array | nojson::JsonValueKind::Object
+    ) {
+        return Err(value.invalid("must be a JSON array or JSON object"));
+    }
+    Template::parse(value)
+}
+
+fn load_mix_file(path: &Path) -> orfail::Result<Vec<MixEntry>> {
+    let text = std::fs::read_to_string(path)
+        .or_fail_with(|e| format!("Failed to read '{}': {e}", path.display()))?;
+    let mut mix = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = MixEntry::parse_json(line).or_fail_with(|e| {
+            format!(
+                "Invalid mix entry at line {} of '{}': {e}",
+                i + 1,
+                path.display()
+            )
+        })?;
+        mix.push(entry);
+    }
+    Ok(mix)
+}
+
+#[derive(Debug)]
+struct ReqCommand {
+    mix: Vec<MixEntry>,
+    interleave: bool,
+
+    // `None` for notifications
+    ids: Option<IdGenerator>,
+
+    notification_rate: Option<f64>,
+
+    // 1 if requests are not batched
+    batch_size: usize,
+
+    data: Option<DataSet>,
+    count: usize,
+    rng: Rng,
+}
+
+impl ReqCommand {
+    fn run(mut self) {
+        let mut selector = MixSelector::new(&self.mix);
+        let mut newest_key = None;
+        let mut batch = Vec::with_capacity(self.batch_size);
+        for seq in 0..self.count as u64 {
+            let entry = &self.mix[selector.next(self.interleave, &mut self.rng)];
+            let id = self
+                .ids
+                .as_ref()
+                .filter(|_| {
+                    self.notification_rate
+                        .is_none_or(|rate| !self.rng.gen_bool(rate))
+                })
+                .map(|ids| ids.generate(seq, &mut self.rng));
+            let (columns, row): (&[String], &[String]) = match &self.data {
+                Some(DataSet::Rows { columns, rows }) => {
+                    (columns, &rows[seq as usize % rows.len()])
+                }
+                _ => (&[], &[]),
+            };
+            let params = match (&entry.params, &self.data) {
+                (Some(template), _) => Some(template.expand(&mut Context {
+                    id: id.clone(),
+                    seq,
+                    columns,
+                    row,
+                    newest_key: &mut newest_key,
+                    rng: &mut self.rng,
+                })),
+                (None, Some(DataSet::Params(params))) => {
+                    Some(Value::Json(params[seq as usize % params.len()].value()))
+                }
+                (None, Some(DataSet::Rows { .. })) => Some(Value::Object(
+                    columns
+                        .iter()
+                        .zip(row)
+                        .map(|(c, v)| (c.as_str(), Value::String(v.clone())))
+                        .collect(),
+                )),
+                (None, None) => None,
+            };
+            let json = nojson::object(|f| {
+                f.member("jsonrpc", "2.0")?;
+                f.member("method", &entry.method)?;
+                if let Some(params) = &params {
+                    f.member("params", params)?;
+                }
+                if let Some(id) = &id {
+                    f.member("id", id)?;
+                }
+                Ok(())
+            });
+            if self.batch_size == 1 {
+                println!("{json}");
+                continue;
+            }
+
+            batch.push(json.to_string());
+            if batch.len() == self.batch_size || seq + 1 == self.count as u64 {
+                println!("[{}]", batch.join(","));
+                batch.clear();
+            }
+        }
+    }
+}
+
+/// Records read from --params-file or --data-file, each of which is used for a request.
+#[derive(Debug)]
+enum DataSet {
+    Params(Vec<nojson::RawJsonOwned>),
+    Rows {
+        columns: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+impl DataSet {
+    fn load_params_file(path: &Path) -> orfail::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .or_fail_with(|e| format!("Failed to read '{}': {e}", path.display()))?;
+        let mut params = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let json = nojson::RawJsonOwned::parse(line)
+                .and_then(|json| {
+                    parse_params(json.value())?;
+                    Ok(json)
+                })
+                .or_fail_with(|e| {
+                    format!(
+                        "Invalid params at line {} of '{}': {e}",
+                        i + 1,
+                        path.display()
+                    )
+                })?;
+            params.push(json);
+        }
+        (!params.is_empty()).or_fail_with(|()| format!("No params in '{}'", path.display()))?;
+        Ok(Self::Params(params))
+    }
+
+    fn load_csv_file(path: &Path) -> orfail::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .or_fail_with(|e| format!("Failed to read '{}': {e}", path.display()))?;
+        let mut records = parse_csv(&text)
+            .map_err(|e| orfail::Failure::new(format!("Invalid CSV in '{}': {e}", path.display())))?
+            .into_iter();
+        let (_, columns) = records
+            .next()
+            .or_fail_with(|()| format!("No header in '{}'", path.display()))?;
+        let mut rows = Vec::new();
+        for (line, row) in records {
+            (row.len() == columns.len()).or_fail_with(|()| {
+                format!(
+                    "Invalid record at line {line} of '{}': expected {} fields but got {}",
+                    path.display(),
+                    columns.len(),
+                    row.len()
+                )
+            })?;
+            rows.push(row);
+        }
+        (!rows.is_empty()).or_fail_with(|()| format!("No records in '{}'", path.display()))?;
+        Ok(Self::Rows { columns, rows })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Params(params) => params.len(),
+            Self::Rows { rows, .. } => rows.len(),
+        }
+    }
+}
+
+/// Parses CSV text (RFC 4180) into records paired with their one-based starting line numbers.
+///
+/// Empty lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start_line = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            match chars.next() {
+                None => break,
+                Some('"') if quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        quoted = false;
+                    }
+                }
+                Some('"') if field.is_empty() => quoted = true,
+                Some('\n') if quoted => {
+                    line += 1;
+                    field.push('\n');
+                }
+                Some(',') if !quoted => fields.push(std::mem::take(&mut field)),
+                Some('\r') if !quoted && chars.peek() == Some(&'\n') => {}
+                Some('\n') => {
+                    line += 1;
+                    break;
+                }
+                Some(c) => field.push(c),
+            }
+        }
+        if quoted {
+            return Err(format!("unclosed quote at line {start_line}"));
+        }
+        if fields.is_empty() && field.is_empty() {
+            continue;
+        }
+        fields.push(field);
+        records.push((start_line, fields));
+    }
+    Ok(records)
+}
+
+/// How the IDs of the generated requests are made.
+#[derive(Debug)]
+enum IdGenerator {
+    Sequential { start: i64, prefix: Option<String> },
+    Uuid { prefix: String },
+}
+
+impl IdGenerator {
+    fn generate(&self, seq: u64, rng: &mut Rng) -> RequestId {
+        match self {
+            Self::Sequential {
+                start,
+                prefix: None,
+            } => RequestId::Number(start + seq as i64),
+            Self::Sequential {
+                start,
+                prefix: Some(prefix),
+            } => RequestId::String(format!("{prefix}{}", start + seq as i64)),
+            Self::Uuid { prefix } => RequestId::String(format!("{prefix}{}", gen_uuid(rng))),
+        }
+    }
+}
+
+/// Method and params of the generated requests, chosen in proportion to the weight.
+#[derive(Debug)]
+struct MixEntry {
+    method: String,
+    params: Option<Template>,
+    weight: f64,
+}
+
+impl MixEntry {
+    fn parse_json(line: &str) -> Result<Self, nojson::JsonParseError> {
+        let json = nojson::RawJson::parse(line)?;
+        let value = json.value();
+        let method = value.to_member("method")?.required()?.try_into()?;
+        let params = value
+            .to_member("params")?
+            .get()
+            .map(parse_params)
+            .transpose()?;
+        let weight = match value.to_member("weight")?.get() {
+            Some(v) => {
+                let weight: f64 = v.try_into()?;
+                if !(weight.is_finite() && weight > 0.0) {
+                    return Err(v.invalid("weight must be a positive number"));
+                }
+                weight
+            }
+            None => 1.0,
+        };
+        Ok(Self {
+            method,
+            params,
+            weight,
+        })
+    }
+}
+
+impl std::str::FromStr for MixEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, params) = match s.split_once('=') {
+            Some((spec, params)) => (spec, Some(params)),
+            None => (s, None),
+        };
+        let (method, weight) = spec
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected METHOD:WEIGHT[=PARAMS]: {s}"))?;
+        let weight: f64 = weight
+            .parse()
+            .map_err(|e| format!("invalid weight {weight:?}: {e}"))?;
+        if !(weight.is_finite() && weight > 0.0) {
+            return Err(format!("weight must be a positive number: {weight}"));
+        }
+        let params = params
+            .map(|params| {
+                let json = nojson::RawJson::parse(params).map_err(|e| e.to_string())?;
+                parse_params(json.value()).map_err(|e| e.to_string())
+            })
+            .transpose()?;
+        Ok(Self {
+            method: method.to_owned(),
+            params,
+            weight,
+        })
+    }
+}
+
+/// Chooses mix entries randomly or by smooth weighted round-robin (for --interleave).
+#[derive(Debug)]
+struct MixSelector {
+    weights: Vec<f64>,
+    total_weight: f64,
+
+    // Accumulated weights of the smooth weighted round-robin
+    current: Vec<f64>,
+}
+
+impl MixSelector {
+    fn new(mix: &[MixEntry]) -> Self {
+        let weights: Vec<f64> = mix.iter().map(|e| e.weight).collect();
+        Self {
+            total_weight: weights.iter().sum(),
+            current: vec![0.0; weights.len()],
+            weights,
+        }
+    }
+
+    fn next(&mut self, interleave: bool, rng: &mut Rng) -> usize {
+        if self.weights.len() == 1 {
+            return 0;
+        }
+
+        if interleave {
+            for (current, weight) in self.current.iter_mut().zip(&self.weights) {
+                *current += weight;
+            }
+            let (i, _) = self
+                .current
+                .iter()
+                .enumerate()
+                .fold(
+                    (0, f64::MIN),
+                    |acc, (i, &c)| if c > acc.1 { (i, c) } else { acc },
+                );
+            self.current[i] -= self.total_weight;
+            return i;
+        }
+
+        let mut r = rng.next_f64() * self.total_weight;
+        for (i, weight) in self.weights.iter().enumerate() {
+            if r < *weight {
+                return i;
+            }
+            r -= weight;
+        }
+        self.weights.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(records: &[(usize, Vec<String>)]) -> Vec<Vec<&str>> {
+        records
+            .iter()
+            .map(|(_, fields)| fields.iter().map(|f| f.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parse_csv_quoting() {
+        let records = parse_csv("name,note\n\"Doe, J\",\"say \"\"hi\"\"\"\n").expect("valid");
+        assert_eq!(
+            fields(&records),
+            [vec!["name", "note"], vec!["Doe, J", "say \"hi\""]]
+        );
+    }
+
+    #[test]
+    fn parse_csv_crlf_and_empty_fields() {
+        let records = parse_csv("a,b,c\r\n,,\r\n1,\"\",3").expect("valid");
+        assert_eq!(
+            fields(&records),
+            [vec!["a", "b", "c"], vec!["", "", ""], vec!["1", "", "3"]]
+        );
+    }
+
+    #[test]
+    fn parse_csv_line_numbers() {
+        let records = parse_csv("a,b\n\n\"multi\nline\",x\r\n\nlast,y\n").expect("valid");
+        assert_eq!(
+            fields(&records),
+            [vec!["a", "b"], vec!["multi\nline", "x"], vec!["last", "y"]]
+        );
+        assert_eq!(
+            records.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            [1, 3, 6]
+        );
+    }
+
+    #[test]
+    fn parse_csv_unclosed_quote() {
+        assert_eq!(
+            parse_csv("a\n\"open,x\ny\n"),
+            Err("unclosed quote at line 2".to_owned())
+        );
+    }
+}
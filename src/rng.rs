@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+/// Small non-cryptographic pseudo random number generator (xorshift64*).
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with SplitMix64 to avoid poor initial states (e.g., zero)
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// Makes an instance seeded by the current time.
+    pub fn from_time() -> Self {
+        let now = std::time::UNIX_EPOCH.elapsed().unwrap_or_default();
+        Self::new(now.as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value in the range `[0.0, 1.0)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns `true` with the given probability.
+    pub fn gen_bool(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    /// Returns a value in the range `[min, max]`.
+    pub fn gen_duration(&mut self, min: Duration, max: Duration) -> Duration {
+        min + (max.saturating_sub(min)).mul_f64(self.next_f64())
+    }
+
+    /// Returns a value sampled from the exponential distribution with the given mean.
+    pub fn gen_exponential(&mut self, mean: Duration) -> Duration {
+        mean.mul_f64(-(1.0 - self.next_f64()).ln())
+    }
+
+    /// Returns a rank in the range `[1, n]` sampled from the Zipf distribution with the given skew (exponent).
+    ///
+    /// This uses rejection-inversion sampling (Hörmann and Derflinger, 1996),
+    /// which takes constant time and memory regardless of `n`.
+    pub fn gen_zipf(&mut self, n: u64, skew: f64) -> u64 {
+        let h = |x: f64| (-skew * x.ln()).exp();
+        let h_integral = |x: f64| {
+            let log_x = x.ln();
+            helper2((1.0 - skew) * log_x) * log_x
+        };
+        let h_integral_inverse = |x: f64| {
+            let t = (x * (1.0 - skew)).max(-1.0);
+            (helper1(t) * x).exp()
+        };
+
+        let h_integral_x1 = h_integral(1.5) - 1.0;
+        let h_integral_n = h_integral(n as f64 + 0.5);
+        let s = 2.0 - h_integral_inverse(h_integral(2.5) - h(2.0));
+        loop {
+            let u = h_integral_n + self.next_f64() * (h_integral_x1 - h_integral_n);
+            let x = h_integral_inverse(u);
+            let k = ((x + 0.5) as u64).clamp(1, n);
+            if k as f64 - x <= s || u >= h_integral(k as f64 + 0.5) - h(k as f64) {
+                return k;
+            }
+        }
+    }
+}
+
+/// Returns `ln(1 + x) / x` (accurate even if `x` is close to zero).
+fn helper1(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.ln_1p() / x
+    } else {
+        1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+    }
+}
+
+/// Returns `(exp(x) - 1) / x` (accurate even if `x` is close to zero).
+fn helper2(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.exp_m1() / x
+    } else {
+        1.0 + x * 0.5 * (1.0 + x / 3.0 * (1.0 + 0.25 * x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the sampled frequencies of the first ranks against the Zipf probability mass function.
+    fn assert_zipf(n: u64, skew: f64) {
+        const SAMPLES: usize = 200_000;
+
+        let mut rng = Rng::new(0);
+        let mut counts = vec![0usize; n as usize + 1];
+        for _ in 0..SAMPLES {
+            let k = rng.gen_zipf(n, skew);
+            assert!((1..=n).contains(&k), "k={k}, n={n}");
+            counts[k as usize] += 1;
+        }
+
+        let normalizer = (1..=n).map(|k| (k as f64).powf(-skew)).sum::<f64>();
+        for k in 1..=n.min(10) {
+            let expected = (k as f64).powf(-skew) / normalizer;
+            let actual = counts[k as usize] as f64 / SAMPLES as f64;
+            assert!(
+                (actual - expected).abs() < 0.005,
+                "n={n}, skew={skew}, k={k}: expected={expected}, actual={actual}"
+            );
+        }
+    }
+
+    #[test]
+    fn gen_zipf_distribution() {
+        assert_zipf(4, 2.0);
+        assert_zipf(1000, 1.0);
+        assert_zipf(100, 0.5);
+        assert_zipf(10, 0.0);
+        assert_zipf(1, 1.0);
+    }
+
+    #[test]
+    fn gen_zipf_large_n() {
+        let mut rng = Rng::new(0);
+        for skew in [0.0, 0.99, 1.0, 1.5] {
+            for _ in 0..1000 {
+                let k = rng.gen_zipf(u64::MAX >> 1, skew);
+                assert!((1..=u64::MAX >> 1).contains(&k));
+            }
+        }
+    }
+}
@@ -1,7 +1,34 @@
-use std::{io::BufRead, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    io::BufRead,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use orfail::OrFail;
 
+use crate::{histogram::Histogram, table::Table, types::JsonPointer};
+
+// Four buckets per power of two, so each bucket is at most 25% wider than its lower bound
+const HISTOGRAM_PRECISION_BITS: u32 = 2;
+
+const HISTOGRAM_BAR_WIDTH: usize = 50;
+
+// Upper limit of the number of time slots kept to calculate the max concurrency
+const MAX_CONCURRENCY_SLOTS: usize = 1 << 20;
+
+// Upper limit of the number of distinct error messages counted
+const MAX_ERROR_MESSAGES: usize = 1000;
+
+const TOP_ERROR_MESSAGES: usize = 5;
+
+// Percentiles of request and response sizes
+const SIZE_PERCENTILES: [f64; 2] = [50.0, 99.0];
+
+// Significance level of the test enabled by --significance
+const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
 pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
     if !noargs::cmd("stats")
         .doc("Calculate statistics from JSON objects outputted by the bench command")
@@ -11,40 +38,1287 @@ pub fn try_run(args: &mut noargs::RawArgs) -> noargs::Result<bool> {
         return Ok(false);
     }
 
+    let percentiles: Vec<f64> = noargs::opt("percentiles")
+        .short('p')
+        .ty("PERCENTILE[,PERCENTILE]*")
+        .doc("Comma-separated latency percentiles to report (e.g., 50,90,99,99.9)")
+        .default("25,50,75")
+        .take(args)
+        .then(|o| parse_percentiles(o.value()))?;
+    let format: OutputFormat = noargs::opt("format")
+        .short('f')
+        .ty("json | table | markdown | csv | prometheus")
+        .doc(concat!(
+            "Output format\n",
+            "\n",
+            "- json: JSON object (or JSON Lines with --interval)\n",
+            "- table: Aligned text table for terminals\n",
+            "- markdown: Markdown table\n",
+            "- csv: CSV with one row per group (or per interval)\n",
+            "- prometheus: Prometheus text exposition format (not available with --interval)"
+        ))
+        .default("json")
+        .take(args)
+        .then(|o| o.value().parse())?;
+    let mut group_by: Option<GroupBy> = noargs::opt("group-by")
+        .short('g')
+        .ty("method | server | source | JSON_POINTER")
+        .doc(concat!(
+            "Also calculate statistics for each group of records\n",
+            "\n",
+            "Records are grouped by the method name, the server address, the input file,\n",
+            "or the value at the JSON pointer (e.g., /params/0) in each record.\n",
+            "If multiple input files are specified, records are also grouped by the input file\n",
+            "(i.e., defaults to `source`), and by the pair of the file and the value\n",
+            "(e.g., \"b1.jsonl: get\") if another grouping is specified."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let interval: Option<Duration> = noargs::opt("interval")
+        .short('i')
+        .ty("SECONDS")
+        .doc(concat!(
+            "Output time-series statistics as JSON Lines instead\n",
+            "\n",
+            "Records are bucketed into intervals of the given length by their start time.\n",
+            "Each line contains the statistics of an interval (and of a group if --group-by is specified).\n",
+            "Intervals without records are output with zero counts."
+        ))
+        .take(args)
+        .present_and_then(|o| {
+            o.value()
+                .parse()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .filter(|d| !d.is_zero())
+                .ok_or("interval must be a positive number of seconds")
+        })?;
+    let histogram = noargs::flag("histogram")
+        .short('H')
+        .doc(concat!(
+            "Output a latency histogram with log-scaled buckets instead\n",
+            "\n",
+            "Each power of two is divided into four buckets.\n",
+            "Bucket bounds are in seconds and the upper bound is exclusive."
+        ))
+        .take(args)
+        .is_present();
+    let histogram_bars = noargs::flag("histogram-bars")
+        .doc("Render the latency histogram as ASCII bars (implies --histogram)")
+        .take(args)
+        .is_present();
+    let latency_by_size = noargs::flag("latency-by-size")
+        .doc(concat!(
+            "Also report latencies for each response size bucket\n",
+            "\n",
+            "Records are bucketed by powers of two of their response byte size,\n",
+            "which shows whether slow calls are simply large ones."
+        ))
+        .take(args)
+        .is_present();
+    let baseline: Option<PathBuf> = noargs::opt("baseline")
+        .short('b')
+        .ty("PATH")
+        .doc(concat!(
+            "Compare the statistics with a baseline run instead\n",
+            "\n",
+            "The baseline is either a JSON object outputted by this command\n",
+            "or JSON Lines outputted by the bench command."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+    let significance = noargs::flag("significance")
+        .doc(concat!(
+            "Test whether latencies differ from the baseline (Mann-Whitney U test)\n",
+            "\n",
+            "The baseline must be bench JSON Lines. The test uses the latency histograms,\n",
+            "so latencies in the same bucket are treated as ties."
+        ))
+        .take(args)
+        .is_present();
+
+    let mut thresholds = Vec::new();
+    if let Some(seconds) = noargs::opt("max-p99")
+        .ty("SECONDS")
+        .doc("Fail if the p99 latency exceeds the given seconds")
+        .take(args)
+        .present_and_then(|o| parse_non_negative(o.value()))?
+    {
+        thresholds.push(Threshold::MaxLatency {
+            percentile: 99.0,
+            seconds,
+        });
+    }
+    while let Some(threshold) = noargs::opt("max-latency")
+        .ty("PERCENTILE=SECONDS")
+        .doc(concat!(
+            "Fail if the latency at the percentile exceeds the given seconds\n",
+            "\n",
+            "This option can be specified multiple times (e.g., --max-latency 50=0.001)."
+        ))
+        .take(args)
+        .present_and_then(|o| parse_latency_threshold(o.value()))?
+    {
+        thresholds.push(threshold);
+    }
+    if let Some(rps) = noargs::opt("min-rps")
+        .ty("REQUESTS_PER_SECOND")
+        .doc("Fail if the throughput is below the given requests per second")
+        .take(args)
+        .present_and_then(|o| parse_non_negative(o.value()))?
+    {
+        thresholds.push(Threshold::MinRps(rps));
+    }
+    if let Some(rate) = noargs::opt("max-error-rate")
+        .ty("RATE")
+        .doc("Fail if the ratio of error responses exceeds the given rate (0.0 to 1.0)")
+        .take(args)
+        .present_and_then(|o| parse_non_negative(o.value()))?
+    {
+        thresholds.push(Threshold::MaxErrorRate(rate));
+    }
+
+    let top_slow: Option<usize> = noargs::opt("top-slow")
+        .ty("N")
+        .doc(concat!(
+            "Also report the N records with the highest latencies\n",
+            "\n",
+            "The method, id, params, server and timestamps of each record are reported\n",
+            "so that the calls can be reproduced."
+        ))
+        .take(args)
+        .present_and_then(|o| o.value().parse())?;
+
+    let mut filter = RecordFilter::default();
+    while let Some(method) = noargs::opt("method")
+        .ty("NAME")
+        .doc("Only use records of the method (can be specified multiple times)")
+        .take(args)
+        .present_and_then(|o| o.value().parse::<String>())?
+    {
+        filter.methods.push(method);
+    }
+    filter.from = noargs::opt("from")
+        .ty("SECONDS")
+        .doc("Only use records started at or after the given seconds from the first record")
+        .take(args)
+        .present_and_then(|o| parse_duration(o.value()))?;
+    filter.until = noargs::opt("until")
+        .ty("SECONDS")
+        .doc("Only use records started before the given seconds from the first record")
+        .take(args)
+        .present_and_then(|o| parse_duration(o.value()))?;
+    filter.slower_than = noargs::opt("slower-than")
+        .ty("SECONDS")
+        .doc("Only use records whose latency is greater than the given seconds")
+        .take(args)
+        .present_and_then(|o| parse_duration(o.value()))?;
+    filter.faster_than = noargs::opt("faster-than")
+        .ty("SECONDS")
+        .doc("Only use records whose latency is less than the given seconds")
+        .take(args)
+        .present_and_then(|o| parse_duration(o.value()))?;
+    filter.only = noargs::opt("only")
+        .ty("success | error")
+        .doc("Only use successful or error records")
+        .take(args)
+        .present_and_then(|o| match o.value() {
+            "success" => Ok(true),
+            "error" => Ok(false),
+            v => Err(format!("expected success or error: {v}")),
+        })?;
+    while let Some(condition) = noargs::opt("where")
+        .ty("JSON_POINTER=VALUE")
+        .doc(concat!(
+            "Only use records whose value at the JSON pointer equals VALUE\n",
+            "\n",
+            "Values are compared in the same way as --group-by keys\n",
+            "(e.g., --where /params/0=foo). This option can be specified multiple times."
+        ))
+        .take(args)
+        .present_and_then(|o| parse_where(o.value()))?
+    {
+        filter.conditions.push(condition);
+    }
+
+    let mut time_shifts = Vec::new();
+    while let Some(time_shift) = noargs::opt("time-shift")
+        .ty("PATH=SECONDS")
+        .doc(concat!(
+            "Shift the timestamps of the records in an input file by the given seconds\n",
+            "\n",
+            "This compensates for clock offsets between machines that ran the bench command.\n",
+            "SECONDS can be negative. This option can be specified multiple times."
+        ))
+        .take(args)
+        .present_and_then(|o| parse_time_shift(o.value()))?
+    {
+        time_shifts.push(time_shift);
+    }
+
+    let input_arg = noargs::arg("[PATH]...").doc(concat!(
+        "Input files containing the output of the bench command (`-` means stdin)\n",
+        "\n",
+        "If no files are specified, stdin is read."
+    ));
+    let mut inputs = Vec::new();
+    while let Some(path) = input_arg
+        .take(args)
+        .present_and_then(|a| a.value().parse::<String>())?
+    {
+        inputs.push(Input {
+            path,
+            time_shift_micros: 0,
+        });
+    }
+
     if args.metadata().help_mode {
         return Ok(true);
     }
 
-    run_stats()?;
+    if inputs.is_empty() {
+        inputs.push(Input {
+            path: "-".to_owned(),
+            time_shift_micros: 0,
+        });
+    }
+    for (path, shift) in time_shifts {
+        let input = inputs
+            .iter_mut()
+            .find(|input| input.path == path)
+            .or_fail_with(|()| format!("--time-shift refers to an unknown input file: {path}"))?;
+        input.time_shift_micros = shift;
+    }
+    if inputs.len() > 1 {
+        group_by = Some(match group_by {
+            Some(GroupBy::Value(p)) => GroupBy::SourceAndValue(p),
+            group_by => group_by.unwrap_or(GroupBy::Source),
+        });
+    }
+
+    let command = StatsCommand {
+        inputs,
+        percentiles,
+        format,
+        group_by,
+        interval,
+        histogram: if histogram_bars {
+            Some(HistogramFormat::Bars)
+        } else if histogram {
+            Some(HistogramFormat::Json)
+        } else {
+            None
+        },
+        baseline,
+        significance,
+        thresholds,
+        latency_by_size,
+        filter,
+        top_slow,
+    };
+    command.run()?;
     Ok(true)
 }
 
-fn run_stats() -> orfail::Result<()> {
-    let stdin = std::io::stdin();
-    let mut stats = Stats::default();
+fn parse_percentiles(s: &str) -> Result<Vec<f64>, String> {
+    s.split(',')
+        .map(|p| {
+            let p: f64 = p
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid percentile {p:?}: {e}"))?;
+            if !(0.0..=100.0).contains(&p) {
+                return Err(format!("percentile must be between 0 and 100: {p}"));
+            }
+            Ok(p)
+        })
+        .collect()
+}
+
+fn parse_time_shift(s: &str) -> Result<(String, i64), String> {
+    let (path, seconds) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected PATH=SECONDS: {s}"))?;
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|e| format!("invalid seconds {seconds:?}: {e}"))?;
+    if !seconds.is_finite() {
+        return Err(format!("invalid seconds: {seconds}"));
+    }
+    Ok((path.to_owned(), (seconds * 1e6).round() as i64))
+}
+
+/// How records are grouped by --group-by.
+#[derive(Debug)]
+enum GroupBy {
+    /// The input file of the record
+    Source,
+
+    /// The value at the JSON pointer in the record
+    Value(JsonPointer),
+
+    /// Both the input file and the value at the JSON pointer (used for multiple input files)
+    ///
+    /// Records belong to the group of the input file and the group of the pair.
+    SourceAndValue(JsonPointer),
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "method" => "/method".parse().map(Self::Value),
+            "server" => "/server".parse().map(Self::Value),
+            "source" => Ok(Self::Source),
+            pointer => pointer.parse().map(Self::Value),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Input {
+    path: String,
+    time_shift_micros: i64,
+}
+
+impl Input {
+    fn open(&self) -> orfail::Result<Box<dyn BufRead>> {
+        if self.path == "-" {
+            return Ok(Box::new(std::io::stdin().lock()));
+        }
+        let file = std::fs::File::open(&self.path)
+            .or_fail_with(|e| format!("failed to open '{}': {e}", self.path))?;
+        Ok(Box::new(std::io::BufReader::new(file)))
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let seconds = parse_non_negative(s)?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("invalid seconds {s:?}: {e}"))
+}
+
+fn parse_where(s: &str) -> Result<(JsonPointer, String), String> {
+    let (pointer, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected JSON_POINTER=VALUE: {s}"))?;
+    Ok((pointer.parse()?, expected_key(value)))
+}
+
+/// Conditions that records must satisfy to be used for statistics.
+#[derive(Debug, Default)]
+struct RecordFilter {
+    methods: Vec<String>,
+    from: Option<Duration>,
+    until: Option<Duration>,
+    slower_than: Option<Duration>,
+    faster_than: Option<Duration>,
+
+    // `Some(true)` for success records only and `Some(false)` for error records only
+    only: Option<bool>,
+
+    // JSON pointer => expected key (see `expected_key()`)
+    conditions: Vec<(JsonPointer, String)>,
+}
+
+impl RecordFilter {
+    /// Returns whether the record satisfies all conditions.
+    ///
+    /// `origin` is the start time of the first record, which --from and --until are relative to.
+    fn matches(&self, record: &Record<'_, '_>, origin: Duration) -> bool {
+        if !self.methods.is_empty() {
+            let method = record
+                .value
+                .to_member("method")
+                .ok()
+                .and_then(|m| m.get())
+                .and_then(|v| v.to_unquoted_string_str().ok());
+            if !method.is_some_and(|m| self.methods.iter().any(|x| *x == m)) {
+                return false;
+            }
+        }
+
+        let offset = record.start_time.saturating_sub(origin);
+        if self.from.is_some_and(|from| offset < from)
+            || self.until.is_some_and(|until| offset >= until)
+        {
+            return false;
+        }
+
+        let latency = record.latency();
+        if self.slower_than.is_some_and(|t| latency <= t)
+            || self.faster_than.is_some_and(|t| latency >= t)
+        {
+            return false;
+        }
+
+        if self
+            .only
+            .is_some_and(|success| success != record.error.is_none())
+        {
+            return false;
+        }
+
+        self.conditions
+            .iter()
+            .all(|(pointer, key)| group_key(pointer.get(record.value)) == *key)
+    }
+}
+
+/// Converts a --where value into the form of [`group_key()`] (JSON strings are unquoted).
+fn expected_key(value: &str) -> String {
+    match nojson::RawJson::parse(value) {
+        Ok(json) if json.value().kind() == nojson::JsonValueKind::String => json
+            .value()
+            .to_unquoted_string_str()
+            .expect("infallible")
+            .into_owned(),
+        _ => value.to_owned(),
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f64, String> {
+    let v: f64 = s
+        .parse()
+        .map_err(|e| format!("invalid number {s:?}: {e}"))?;
+    if !(v >= 0.0 && v.is_finite()) {
+        return Err(format!("must be a non-negative number: {s}"));
+    }
+    Ok(v)
+}
+
+fn parse_latency_threshold(s: &str) -> Result<Threshold, String> {
+    let (percentile, seconds) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PERCENTILE=SECONDS: {s}"))?;
+    let percentile = parse_percentiles(percentile)?;
+    let [percentile] = percentile[..] else {
+        return Err(format!("expected a single percentile: {s}"));
+    };
+    Ok(Threshold::MaxLatency {
+        percentile,
+        seconds: parse_non_negative(seconds)?,
+    })
+}
+
+/// SLO assertion evaluated against the statistics of all records.
+#[derive(Debug, Clone, Copy)]
+enum Threshold {
+    MaxLatency { percentile: f64, seconds: f64 },
+    MinRps(f64),
+    MaxErrorRate(f64),
+}
+
+impl Threshold {
+    /// Returns a message describing the violation if the stats do not satisfy this threshold.
+    fn check(self, stats: &Stats) -> Option<String> {
+        match self {
+            Self::MaxLatency {
+                percentile,
+                seconds,
+            } => {
+                let latency = stats.calculate_latency_percentile(percentile);
+                (latency > seconds)
+                    .then(|| format!("p{percentile} latency is {latency} seconds (max: {seconds})"))
+            }
+            Self::MinRps(min) => {
+                let rps = stats.calculate_rps(stats.calculate_duration()) as f64;
+                (rps < min).then(|| format!("throughput is {rps} requests/second (min: {min})"))
+            }
+            Self::MaxErrorRate(max) => {
+                let rate = stats.calculate_error_rate();
+                (rate > max).then(|| format!("error rate is {rate} (max: {max})"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    Table,
+    Markdown,
+    Csv,
+    Prometheus,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "table" => Ok(Self::Table),
+            "markdown" => Ok(Self::Markdown),
+            "csv" => Ok(Self::Csv),
+            "prometheus" => Ok(Self::Prometheus),
+            _ => Err(format!("unknown output format: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum HistogramFormat {
+    Json,
+    Bars,
+}
+
+struct StatsCommand {
+    inputs: Vec<Input>,
+    percentiles: Vec<f64>,
+    format: OutputFormat,
+    group_by: Option<GroupBy>,
+    interval: Option<Duration>,
+    histogram: Option<HistogramFormat>,
+    baseline: Option<PathBuf>,
+    significance: bool,
+    thresholds: Vec<Threshold>,
+    latency_by_size: bool,
+    filter: RecordFilter,
+    top_slow: Option<usize>,
+}
+
+impl StatsCommand {
+    fn run(self) -> orfail::Result<()> {
+        (self.histogram.is_none() || self.interval.is_none())
+            .or_fail_with(|()| "--histogram cannot be combined with --interval".to_owned())?;
+        (self.baseline.is_none() || self.inputs.len() == 1).or_fail_with(|()| {
+            "--baseline cannot be combined with multiple input files".to_owned()
+        })?;
+        (self.baseline.is_none()
+            || (self.histogram.is_none() && self.interval.is_none() && self.group_by.is_none()))
+        .or_fail_with(|()| {
+            "--baseline cannot be combined with --histogram, --interval or --group-by".to_owned()
+        })?;
+        (!self.significance || self.baseline.is_some())
+            .or_fail_with(|()| "--significance requires --baseline".to_owned())?;
+        (self.format == OutputFormat::Json
+            || (self.histogram.is_none() && self.baseline.is_none()))
+        .or_fail_with(|()| {
+            "--format cannot be combined with --histogram or --baseline".to_owned()
+        })?;
+        (self.format != OutputFormat::Prometheus || self.interval.is_none()).or_fail_with(
+            |()| "--format prometheus cannot be combined with --interval".to_owned(),
+        )?;
+        (self.top_slow.is_none()
+            || (self.format == OutputFormat::Json
+                && self.interval.is_none()
+                && self.histogram.is_none()
+                && self.baseline.is_none()))
+        .or_fail_with(|()| {
+            "--top-slow cannot be combined with --interval, --histogram, --baseline or --format"
+                .to_owned()
+        })?;
+
+        let mut stats = self.new_stats();
+        let mut groups = BTreeMap::new();
+        let mut time_series = self
+            .interval
+            .map(|interval| TimeSeries::new(interval, self.percentiles.clone()));
+        let mut slowest = self.top_slow.map(SlowestCalls::new);
+
+        let mut filter_origin = None;
+        for input in &self.inputs {
+            for (i, line) in input.open()?.lines().enumerate() {
+                let line = line.or_fail()?;
+                let invalid = |e: nojson::JsonParseError| {
+                    format!("invalid record at line {} of '{}': {e}", i + 1, input.path)
+                };
+                let json = nojson::RawJson::parse(&line).or_fail_with(invalid)?;
+                let record =
+                    Record::parse(json.value(), input.time_shift_micros).or_fail_with(invalid)?;
+                let origin = *filter_origin.get_or_insert(record.start_time);
+                if !self.filter.matches(&record, origin) {
+                    continue;
+                }
+                let record_groups = match &self.group_by {
+                    None => Vec::new(),
+                    Some(GroupBy::Source) => vec![input.path.clone()],
+                    Some(GroupBy::Value(p)) => vec![group_key(p.get(record.value))],
+                    Some(GroupBy::SourceAndValue(p)) => vec![
+                        input.path.clone(),
+                        format!("{}: {}", input.path, group_key(p.get(record.value))),
+                    ],
+                };
+
+                if let Some(time_series) = &mut time_series {
+                    time_series.handle_record(&record, None);
+                    for group in record_groups {
+                        time_series.handle_record(&record, Some(group));
+                    }
+                    if !self.thresholds.is_empty() {
+                        stats.handle_record(&record);
+                    }
+                    continue;
+                }
+
+                stats.handle_record(&record);
+                if let Some(slowest) = &mut slowest {
+                    slowest.handle_record(&record);
+                }
+                for group in record_groups {
+                    groups
+                        .entry(group)
+                        .or_insert_with(|| self.new_stats())
+                        .handle_record(&record);
+                }
+            }
+        }
+
+        if let Some(time_series) = time_series {
+            if self.format == OutputFormat::Json {
+                for line in time_series.lines() {
+                    println!("{line}");
+                }
+            } else {
+                print!("{}", self.format_rows(time_series.metric_rows()));
+            }
+            return self.check_thresholds(&stats);
+        }
+
+        if self.format != OutputFormat::Json {
+            let rows = std::iter::once((None, stats.metrics()))
+                .chain(groups.iter().map(|(g, s)| (Some(g.as_str()), s.metrics())))
+                .collect::<Vec<_>>();
+            match self.format {
+                OutputFormat::Table => print!("{}", transposed_table(&rows).to_text()),
+                OutputFormat::Markdown => print!("{}", transposed_table(&rows).to_markdown()),
+                OutputFormat::Prometheus => {
+                    // The summary of latencies also needs their sum and count
+                    let rows = rows
+                        .into_iter()
+                        .zip(std::iter::once(&stats).chain(groups.values()))
+                        .map(|((group, mut metrics), stats)| {
+                            metrics
+                                .push(("latency_sum".to_owned(), stats.latency_sum.as_secs_f64()));
+                            metrics
+                                .push(("latency_count".to_owned(), stats.latencies.count() as f64));
+                            (group, metrics)
+                        })
+                        .collect::<Vec<_>>();
+                    print!("{}", prometheus_text(&rows));
+                }
+                _ => print!("{}", self.format_rows(rows)),
+            }
+        } else if let Some(path) = &self.baseline {
+            self.compare(&stats, path).or_fail()?;
+        } else if let Some(format) = self.histogram {
+            print_histogram(format, &stats, &groups);
+        } else if self.group_by.is_some() || slowest.is_some() {
+            let json = nojson::json(|f| {
+                f.set_indent_size(2);
+                f.set_spacing(true);
+                f.object(|f| {
+                    stats.fmt_members(f)?;
+                    if self.group_by.is_some() {
+                        f.member("groups", &groups)?;
+                    }
+                    if let Some(slowest) = &slowest {
+                        f.member("slowest", slowest)?;
+                    }
+                    Ok(())
+                })
+            });
+            println!("{json}");
+        } else {
+            println!("{}", nojson::Json(&stats));
+        }
+        self.check_thresholds(&stats)
+    }
+
+    /// Formats metrics as a table with one row per group (or per interval).
+    fn format_rows(&self, rows: Vec<MetricRow<'_>>) -> String {
+        let Some((_, first)) = rows.first() else {
+            return String::new();
+        };
+        let grouped = self.group_by.is_some();
+
+        let mut header = Vec::new();
+        if grouped {
+            header.push("group".to_owned());
+        }
+        header.extend(first.iter().map(|(name, _)| name.clone()));
+
+        let mut table = Table::new(header);
+        for (group, metrics) in &rows {
+            let mut row = Vec::new();
+            if grouped {
+                row.push(group.unwrap_or_default().to_owned());
+            }
+            row.extend(metrics.iter().map(|(_, v)| v.to_string()));
+            table.push_row(row);
+        }
+
+        match self.format {
+            OutputFormat::Table => table.to_text(),
+            OutputFormat::Markdown => table.to_markdown(),
+            _ => table.to_csv(),
+        }
+    }
+
+    fn check_thresholds(&self, stats: &Stats) -> orfail::Result<()> {
+        let violations = self
+            .thresholds
+            .iter()
+            .filter_map(|t| t.check(stats))
+            .collect::<Vec<_>>();
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!("{} threshold(s) violated:", violations.len());
+        for violation in violations {
+            message.push_str("\n  - ");
+            message.push_str(&violation);
+        }
+        Err(orfail::Failure::new(message))
+    }
+
+    fn compare(&self, stats: &Stats, baseline_path: &Path) -> orfail::Result<()> {
+        let text = std::fs::read_to_string(baseline_path)
+            .or_fail_with(|e| format!("failed to read '{}': {e}", baseline_path.display()))?;
+
+        // A stats JSON object has the "detail" member, unlike bench records
+        let stats_json = nojson::RawJson::parse(&text)
+            .ok()
+            .filter(|json| matches!(json.value().to_member("detail"), Ok(m) if m.get().is_some()));
+        let (baseline, baseline_stats) = if let Some(json) = stats_json {
+            let summary = Summary::from_stats_json(json.value())
+                .or_fail_with(|e| format!("invalid baseline '{}': {e}", baseline_path.display()))?;
+            (summary, None)
+        } else {
+            let mut baseline_stats = self.new_stats();
+            for (i, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                nojson::RawJson::parse(line)
+                    .and_then(|json| {
+                        baseline_stats.handle_record(&Record::parse(json.value(), 0)?);
+                        Ok(())
+                    })
+                    .or_fail_with(|e| {
+                        format!(
+                            "invalid baseline record at line {} of '{}': {e}",
+                            i + 1,
+                            baseline_path.display()
+                        )
+                    })?;
+            }
+            (Summary::from_stats(&baseline_stats), Some(baseline_stats))
+        };
+
+        let significance = if self.significance {
+            let baseline_stats = baseline_stats.as_ref().or_fail_with(|()| {
+                "--significance requires bench JSON Lines as the baseline".to_owned()
+            })?;
+            Some(MannWhitneyU::test(
+                &baseline_stats.latencies,
+                &stats.latencies,
+            ))
+        } else {
+            None
+        };
+
+        let current = Summary::from_stats(stats);
+        let comparison = nojson::json(|f| {
+            f.set_indent_size(2);
+            f.set_spacing(true);
+            f.object(|f| {
+                f.member(
+                    "requests_per_second",
+                    Delta::new(baseline.requests_per_second, current.requests_per_second),
+                )?;
+                f.member(
+                    "error_rate",
+                    Delta::new(baseline.error_rate, current.error_rate),
+                )?;
+                f.member(
+                    "latency",
+                    nojson::object(|f| {
+                        for (name, value) in &current.latency {
+                            let baseline_value = baseline
+                                .latency
+                                .iter()
+                                .find(|(n, _)| n == name)
+                                .map(|(_, v)| *v);
+                            f.member(name, Delta::new(baseline_value, Some(*value)))?;
+                        }
+                        Ok(())
+                    }),
+                )?;
+                if let Some(result) = &significance {
+                    f.member("significance", result)?;
+                }
+                Ok(())
+            })
+        });
+        println!("{comparison}");
+        Ok(())
+    }
+
+    fn new_stats(&self) -> Stats {
+        Stats {
+            percentiles: self.percentiles.clone(),
+            latency_by_response_size: self.latency_by_size.then(BTreeMap::new),
+            ..Stats::default()
+        }
+    }
+}
+
+/// Flat metrics of all records (`None`) or of a group.
+type MetricRow<'a> = (Option<&'a str>, Vec<(String, f64)>);
+
+/// Builds a table with one row per metric and one column per group (the first column is for all records).
+fn transposed_table(rows: &[MetricRow<'_>]) -> Table {
+    let mut header = vec!["metric".to_owned()];
+    header.extend(rows.iter().map(|(g, _)| g.unwrap_or("(all)").to_owned()));
+
+    let mut table = Table::new(header);
+    let Some((_, first)) = rows.first() else {
+        return table;
+    };
+    for (i, (name, _)) in first.iter().enumerate() {
+        let mut row = vec![name.clone()];
+        row.extend(rows.iter().map(|(_, metrics)| metrics[i].1.to_string()));
+        table.push_row(row);
+    }
+    table
+}
+
+/// Formats metrics in the Prometheus text exposition format.
+///
+/// Latency percentiles (including min and max) become the `quantile` label of
+/// the `jlot_latency_seconds` summary (`latency_sum` and `latency_count` become its `_sum` and `_count`)
+/// and success/error counts become the `result` label of the `jlot_requests` counter (`_total`).
+/// Metrics of each group have the `group` label.
+fn prometheus_text(rows: &[MetricRow<'_>]) -> String {
+    // (family, type, samples)
+    let mut families: Vec<(String, &str, Vec<String>)> = Vec::new();
+    for (group, metrics) in rows {
+        for (name, value) in metrics {
+            let (family, suffix, ty, label) = match name.as_str() {
+                "success" | "error" => (
+                    "jlot_requests".to_owned(),
+                    "_total",
+                    "counter",
+                    Some(("result", name.clone())),
+                ),
+                "latency_min" => (
+                    "jlot_latency_seconds".to_owned(),
+                    "",
+                    "summary",
+                    Some(("quantile", "0".to_owned())),
+                ),
+                "latency_max" => (
+                    "jlot_latency_seconds".to_owned(),
+                    "",
+                    "summary",
+                    Some(("quantile", "1".to_owned())),
+                ),
+                "latency_sum" => ("jlot_latency_seconds".to_owned(), "_sum", "summary", None),
+                "latency_count" => ("jlot_latency_seconds".to_owned(), "_count", "summary", None),
+                "latency_stddev" => ("jlot_latency_stddev_seconds".to_owned(), "", "gauge", None),
+                _ => match name
+                    .strip_prefix("latency_p")
+                    .and_then(|p| p.parse::<f64>().ok())
+                {
+                    Some(p) => (
+                        "jlot_latency_seconds".to_owned(),
+                        "",
+                        "summary",
+                        Some(("quantile", (p / 100.0).to_string())),
+                    ),
+                    None => (format!("jlot_{name}"), "", "gauge", None),
+                },
+            };
+
+            let labels = group
+                .map(|g| ("group", g.to_owned()))
+                .into_iter()
+                .chain(label)
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(&v)))
+                .collect::<Vec<_>>();
+            let sample = if labels.is_empty() {
+                format!("{family}{suffix} {value}")
+            } else {
+                format!("{family}{suffix}{{{}}} {value}", labels.join(","))
+            };
+
+            if let Some((_, _, samples)) = families.iter_mut().find(|(f, _, _)| *f == family) {
+                samples.push(sample);
+            } else {
+                families.push((family, ty, vec![sample]));
+            }
+        }
+    }
+
+    let mut text = String::new();
+    for (family, ty, samples) in families {
+        text.push_str(&format!("# TYPE {family} {ty}\n"));
+        for sample in samples {
+            text.push_str(&sample);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+fn escape_label_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Values compared by --baseline.
+#[derive(Debug)]
+struct Summary {
+    requests_per_second: Option<f64>,
+    error_rate: Option<f64>,
+
+    // Latency name (e.g., "p99") => seconds
+    latency: Vec<(String, f64)>,
+}
+
+impl Summary {
+    fn from_stats(stats: &Stats) -> Self {
+        let duration = stats.calculate_duration();
+        let latency_stats = stats.calculate_latency_stats();
+
+        let mut latency = vec![("min".to_owned(), latency_stats.min)];
+        for (p, value) in &latency_stats.percentiles {
+            latency.push((format!("p{p}"), *value));
+        }
+        latency.push(("max".to_owned(), latency_stats.max));
+        latency.push(("avg".to_owned(), latency_stats.avg));
+        latency.push(("stddev".to_owned(), latency_stats.stddev));
+
+        Self {
+            requests_per_second: Some(stats.calculate_rps(duration) as f64),
+            error_rate: Some(stats.calculate_error_rate()),
+            latency,
+        }
+    }
+
+    fn from_stats_json(
+        value: nojson::RawJsonValue<'_, '_>,
+    ) -> Result<Self, nojson::JsonParseError> {
+        let detail = value.to_member("detail")?.required()?;
+        let count = detail.to_member("count")?.required()?;
+        let success: f64 = count.to_member("success")?.required()?.try_into()?;
+        let error: f64 = count.to_member("error")?.required()?.try_into()?;
+
+        let mut latency = Vec::new();
+        if let Some(latency_value) = detail.to_member("latency")?.get() {
+            for (name, v) in latency_value.to_object()? {
+                let name = name.to_unquoted_string_str()?.into_owned();
+                latency.push((name, v.try_into()?));
+            }
+        }
+        if let Some(avg) = value.to_member("avg_latency_seconds")?.get() {
+            latency.push(("avg".to_owned(), avg.try_into()?));
+        }
+
+        Ok(Self {
+            requests_per_second: value
+                .to_member("requests_per_second")?
+                .get()
+                .map(f64::try_from)
+                .transpose()?,
+            error_rate: Some(if success + error > 0.0 {
+                error / (success + error)
+            } else {
+                0.0
+            }),
+            latency,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Delta {
+    baseline: Option<f64>,
+    current: Option<f64>,
+}
+
+impl Delta {
+    fn new(baseline: Option<f64>, current: Option<f64>) -> Self {
+        Self { baseline, current }
+    }
+}
+
+impl nojson::DisplayJson for Delta {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        let delta = self.baseline.zip(self.current).map(|(b, c)| c - b);
+        let delta_percent = self
+            .baseline
+            .zip(delta)
+            .filter(|(b, _)| *b != 0.0)
+            .map(|(b, d)| (d / b * 10000.0).round() / 100.0);
+
+        no_indent_object(|f| {
+            f.member("baseline", self.baseline)?;
+            f.member("current", self.current)?;
+            f.member("delta", delta.map(round_to_nanos))?;
+            f.member("delta_percent", delta_percent)
+        })
+        .fmt(f)
+    }
+}
+
+/// Result of the two-sided Mann-Whitney U test (normal approximation with the tie correction).
+#[derive(Debug)]
+struct MannWhitneyU {
+    z: f64,
+    p_value: f64,
+}
+
+impl MannWhitneyU {
+    fn test(baseline: &Histogram, current: &Histogram) -> Self {
+        let n1 = baseline.count() as f64;
+        let n2 = current.count() as f64;
+        let n = n1 + n2;
+
+        // Rank sum of the baseline, where latencies in the same bucket share their average rank
+        let mut rank_sum = 0.0;
+        let mut tie_correction = 0.0;
+        let mut seen = 0.0;
+        let len = baseline.counts().len().max(current.counts().len());
+        for i in 0..len {
+            let a = baseline.counts().get(i).copied().unwrap_or(0) as f64;
+            let b = current.counts().get(i).copied().unwrap_or(0) as f64;
+            let t = a + b;
+            rank_sum += a * (seen + (t + 1.0) / 2.0);
+            tie_correction += t * t * t - t;
+            seen += t;
+        }
+
+        let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+        let mean = n1 * n2 / 2.0;
+        let variance = if n > 1.0 {
+            n1 * n2 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)))
+        } else {
+            0.0
+        };
+        if variance <= 0.0 {
+            return Self {
+                z: 0.0,
+                p_value: 1.0,
+            };
+        }
+
+        // Note that a positive z means the current latencies tend to be larger
+        let z = (mean - u) / variance.sqrt();
+        Self {
+            z,
+            p_value: erfc(z.abs() / std::f64::consts::SQRT_2),
+        }
+    }
+}
+
+impl nojson::DisplayJson for MannWhitneyU {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        no_indent_object(|f| {
+            f.member("test", "mann-whitney-u")?;
+            f.member("z", (self.z * 1000.0).round() / 1000.0)?;
+            f.member("p_value", self.p_value)?;
+            f.member("significant", self.p_value < SIGNIFICANCE_LEVEL)
+        })
+        .fmt(f)
+    }
+}
+
+/// Complementary error function (Abramowitz and Stegun 7.1.26, absolute error below 1.5e-7).
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erfc = poly * (-x * x).exp();
+    if x < 0.0 { 2.0 - erfc } else { erfc }
+}
+
+fn print_histogram(format: HistogramFormat, stats: &Stats, groups: &BTreeMap<String, Stats>) {
+    let histogram = stats.latency_histogram();
+    let group_histograms = groups
+        .iter()
+        .map(|(group, stats)| (group, stats.latency_histogram()))
+        .collect::<BTreeMap<_, _>>();
+
+    match format {
+        HistogramFormat::Json => {
+            let json = nojson::json(|f| {
+                f.set_indent_size(2);
+                f.set_spacing(true);
+                f.object(|f| {
+                    fmt_histogram_members(f, &histogram)?;
+                    if !group_histograms.is_empty() {
+                        f.member(
+                            "groups",
+                            nojson::json(|f| {
+                                f.object(|f| {
+                                    for (group, histogram) in &group_histograms {
+                                        f.member(
+                                            group,
+                                            nojson::object(|f| fmt_histogram_members(f, histogram)),
+                                        )?;
+                                    }
+                                    Ok(())
+                                })
+                            }),
+                        )?;
+                    }
+                    Ok(())
+                })
+            });
+            println!("{json}");
+        }
+        HistogramFormat::Bars => {
+            print_histogram_bars(&histogram);
+            for (group, histogram) in &group_histograms {
+                println!();
+                println!("{group}:");
+                print_histogram_bars(histogram);
+            }
+        }
+    }
+}
+
+fn fmt_histogram_members(
+    f: &mut nojson::JsonObjectFormatter<'_, '_, '_>,
+    histogram: &Histogram,
+) -> std::fmt::Result {
+    f.member("count", histogram.count())?;
+    f.member(
+        "buckets",
+        nojson::array(|f| {
+            for (lower, upper, count) in histogram.buckets() {
+                f.element(no_indent_object(|f| {
+                    f.member("lower_seconds", micros_to_seconds(lower))?;
+                    f.member("upper_seconds", micros_to_seconds(upper))?;
+                    f.member("count", count)
+                }))?;
+            }
+            Ok(())
+        }),
+    )
+}
 
-    let reader = stdin.lock();
-    for line in reader.lines() {
-        let line = line.or_fail()?;
-        let json = nojson::RawJson::parse(&line).or_fail()?;
-        stats.handle_output(json.value()).or_fail()?;
+fn print_histogram_bars(histogram: &Histogram) {
+    let total = histogram.count();
+    let max_count = histogram.buckets().map(|(_, _, c)| c).max().unwrap_or(0);
+    let count_width = max_count.to_string().len();
+    for (lower, upper, count) in histogram.buckets() {
+        // Round up so that non-empty buckets are always visible
+        let bar_len = (count as usize * HISTOGRAM_BAR_WIDTH).div_ceil(max_count as usize);
+        println!(
+            "[{:.6}, {:.6}) |{:<HISTOGRAM_BAR_WIDTH$}| {count:>count_width$} ({:5.1}%)",
+            micros_to_seconds(lower),
+            micros_to_seconds(upper),
+            "#".repeat(bar_len),
+            count as f64 * 100.0 / total as f64,
+        );
     }
+}
 
-    stats.latencies.sort_unstable();
-    stats.start_end_times.sort_unstable();
+fn micros_to_seconds(micros: u64) -> f64 {
+    Duration::from_micros(micros).as_secs_f64()
+}
 
-    println!("{}", nojson::Json(&stats));
-    Ok(())
+fn group_key(value: Option<nojson::RawJsonValue<'_, '_>>) -> String {
+    match value {
+        None => "null".to_owned(),
+        Some(v) if v.kind() == nojson::JsonValueKind::String => {
+            v.to_unquoted_string_str().expect("infallible").into_owned()
+        }
+        Some(v) => v.to_string(),
+    }
 }
 
+/// Statistics of bench records, calculated in a single pass with bounded memory.
+///
+/// Latency percentiles are estimated from a [`Histogram`] with a relative error of at most `1 / 256`
+/// (latencies below 128 microseconds are exact). Other latency values are exact.
+/// See [`ConcurrencyTracker`] for the error bound of the max concurrency.
 #[derive(Debug, Default)]
 struct Stats {
     success_count: usize,
     error_count: usize,
-    start_end_times: Vec<(Duration, Duration)>,
-    latencies: Vec<Duration>,
+    time_range: Option<(Duration, Duration)>,
+    latencies: Histogram,
+    latency_sum: Duration,
+    latency_mean: f64,
+    latency_m2: f64,
+    concurrency: ConcurrencyTracker,
+    errors: ErrorStats,
     request_bytes: u64,
     response_bytes: u64,
+    request_sizes: Histogram,
+    response_sizes: Histogram,
+    percentiles: Vec<f64>,
+
+    // Response size bucket (see `size_bucket()`) => latencies (enabled by --latency-by-size)
+    latency_by_response_size: Option<BTreeMap<u32, LatencySummary>>,
+}
+
+/// Latency histogram and sum of a subset of records.
+#[derive(Debug, Default)]
+struct LatencySummary {
+    latencies: Histogram,
+    latency_sum: Duration,
+}
+
+impl LatencySummary {
+    fn record(&mut self, latency: Duration) {
+        self.latencies.record(latency.as_micros() as u64);
+        self.latency_sum += latency;
+    }
+
+    /// Calculates the same values as [`Stats::calculate_latency_stats()`] except for the standard deviation.
+    fn calculate_latency_stats(&self, percentiles: &[f64]) -> LatencyStats {
+        let (Some(min), Some(max)) = (self.latencies.min(), self.latencies.max()) else {
+            return LatencyStats {
+                percentiles: percentiles.iter().map(|&p| (p, 0.0)).collect(),
+                ..LatencyStats::default()
+            };
+        };
+
+        let len = self.latencies.count();
+        let avg = Duration::from_nanos((self.latency_sum.as_nanos() / u128::from(len)) as u64);
+        LatencyStats {
+            min: micros_to_seconds(min),
+            percentiles: percentiles
+                .iter()
+                .map(|&p| (p, latency_percentile(&self.latencies, p)))
+                .collect(),
+            max: micros_to_seconds(max),
+            avg: avg.as_secs_f64(),
+            stddev: 0.0,
+        }
+    }
+}
+
+/// Returns the index of the power-of-two bucket (`0` for zero, `[2^(i-1), 2^i)` otherwise) the size belongs to.
+fn size_bucket(size: u64) -> u32 {
+    u64::BITS - size.leading_zeros()
+}
+
+fn size_bucket_range(bucket: u32) -> (u64, u64) {
+    match bucket {
+        0 => (0, 0),
+        _ => {
+            let min = 1u64 << (bucket - 1);
+            (min, min - 1 + min)
+        }
+    }
+}
+
+fn latency_percentile(latencies: &Histogram, percentile: f64) -> f64 {
+    round_to_nanos(latencies.percentile(percentile).unwrap_or_default() / 1e6)
+}
+
+fn size_percentile(sizes: &Histogram, percentile: f64) -> u64 {
+    sizes.percentile(percentile).unwrap_or_default().round() as u64
 }
 
 impl Stats {
@@ -53,12 +1327,9 @@ impl Stats {
     }
 
     fn calculate_duration(&self) -> Duration {
-        let min_start = self.start_end_times.iter().map(|(s, _)| *s).min();
-        let max_end = self.start_end_times.iter().map(|(_, e)| *e).max();
-
-        match (min_start, max_end) {
-            (Some(start), Some(end)) => end.saturating_sub(start),
-            _ => Duration::ZERO,
+        match self.time_range {
+            Some((start, end)) => end.saturating_sub(start),
+            None => Duration::ZERO,
         }
     }
 
@@ -72,6 +1343,15 @@ impl Stats {
         }
     }
 
+    fn calculate_error_rate(&self) -> f64 {
+        let request_count = self.request_count();
+        if request_count > 0 {
+            self.error_count as f64 / request_count as f64
+        } else {
+            0.0
+        }
+    }
+
     fn calculate_avg_request_size(&self) -> f64 {
         let request_count = self.request_count();
         if request_count > 0 {
@@ -91,36 +1371,48 @@ impl Stats {
     }
 
     fn calculate_latency_stats(&self) -> LatencyStats {
-        if self.latencies.is_empty() {
-            return LatencyStats::default();
-        }
+        let (Some(min), Some(max)) = (self.latencies.min(), self.latencies.max()) else {
+            return LatencyStats {
+                percentiles: self.percentiles.iter().map(|&p| (p, 0.0)).collect(),
+                ..LatencyStats::default()
+            };
+        };
 
-        let len = self.latencies.len();
+        let len = self.latencies.count();
+        let avg = Duration::from_nanos((self.latency_sum.as_nanos() / u128::from(len)) as u64);
+        let variance = self.latency_m2 / len as f64;
 
         LatencyStats {
-            min: self.latencies[0].as_secs_f64(),
-            p25: self.latencies[len / 4].as_secs_f64(),
-            p50: self.latencies[len / 2].as_secs_f64(),
-            p75: self.latencies[len * 3 / 4].as_secs_f64(),
-            max: self.latencies[len - 1].as_secs_f64(),
-            avg: (self.latencies.iter().sum::<Duration>() / len as u32).as_secs_f64(),
+            min: micros_to_seconds(min),
+            percentiles: self
+                .percentiles
+                .iter()
+                .map(|&p| (p, self.calculate_latency_percentile(p)))
+                .collect(),
+            max: micros_to_seconds(max),
+            avg: avg.as_secs_f64(),
+            stddev: round_to_nanos(variance.sqrt()),
         }
     }
 
-    fn calculate_max_concurrency(&self) -> usize {
-        let mut max_concurrency = 0;
-        for i in 0..self.start_end_times.len() {
-            let (start, _) = self.start_end_times[i];
-            let concurrency = self.start_end_times[..i]
-                .iter()
-                .rev()
-                .take_while(|(_, end)| start < *end)
-                .count()
-                + 1;
-            max_concurrency = max_concurrency.max(concurrency);
+    fn calculate_latency_percentile(&self, percentile: f64) -> f64 {
+        latency_percentile(&self.latencies, percentile)
+    }
+
+    fn calculate_bytes_per_second(&self, bytes: u64, duration: Duration) -> u64 {
+        if duration > Duration::ZERO {
+            (bytes as f64 / duration.as_secs_f64()).round() as u64
+        } else {
+            0
         }
+    }
+
+    fn latency_histogram(&self) -> Histogram {
+        self.latencies.coarsen(HISTOGRAM_PRECISION_BITS)
+    }
 
-        max_concurrency
+    fn calculate_max_concurrency(&self) -> usize {
+        self.concurrency.max_concurrency()
     }
 }
 
@@ -128,7 +1420,67 @@ impl nojson::DisplayJson for Stats {
     fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
         f.set_indent_size(2);
         f.set_spacing(true);
+        f.object(|f| self.fmt_members(f))
+    }
+}
+
+impl Stats {
+    /// Returns the values of [`Stats::fmt_members()`] as flat `(name, value)` pairs (except for the error breakdown).
+    fn metrics(&self) -> Vec<(String, f64)> {
+        let duration = self.calculate_duration();
+        let latency_stats = self.calculate_latency_stats();
 
+        let mut metrics = vec![
+            ("elapsed_seconds".to_owned(), duration.as_secs_f64()),
+            (
+                "requests_per_second".to_owned(),
+                self.calculate_rps(duration) as f64,
+            ),
+            ("avg_latency_seconds".to_owned(), latency_stats.avg),
+            ("success".to_owned(), self.success_count as f64),
+            ("error".to_owned(), self.error_count as f64),
+            (
+                "request_avg_bytes".to_owned(),
+                self.calculate_avg_request_size().round(),
+            ),
+            (
+                "response_avg_bytes".to_owned(),
+                self.calculate_avg_response_size().round(),
+            ),
+        ];
+        for (name, sizes) in [
+            ("request", &self.request_sizes),
+            ("response", &self.response_sizes),
+        ] {
+            for p in SIZE_PERCENTILES {
+                metrics.push((
+                    format!("{name}_p{p}_bytes"),
+                    size_percentile(sizes, p) as f64,
+                ));
+            }
+            metrics.push((
+                format!("{name}_max_bytes"),
+                sizes.max().unwrap_or_default() as f64,
+            ));
+        }
+        metrics.push((
+            "request_bytes_per_second".to_owned(),
+            self.calculate_bytes_per_second(self.request_bytes, duration) as f64,
+        ));
+        metrics.push((
+            "response_bytes_per_second".to_owned(),
+            self.calculate_bytes_per_second(self.response_bytes, duration) as f64,
+        ));
+        push_latency_metrics(&mut metrics, &latency_stats);
+        metrics.push(("latency_stddev".to_owned(), latency_stats.stddev));
+        metrics.push((
+            "concurrency_max".to_owned(),
+            self.calculate_max_concurrency() as f64,
+        ));
+        metrics
+    }
+
+    fn fmt_members(&self, f: &mut nojson::JsonObjectFormatter<'_, '_, '_>) -> std::fmt::Result {
         let duration = self.calculate_duration();
         let rps = self.calculate_rps(duration);
         let latency_stats = self.calculate_latency_stats();
@@ -136,31 +1488,29 @@ impl nojson::DisplayJson for Stats {
         let avg_response_size = self.calculate_avg_response_size();
         let max_concurrency = self.calculate_max_concurrency();
 
-        f.object(|f| {
-            f.member("elapsed_seconds", duration.as_secs_f64())?;
-            f.member("requests_per_second", rps)?;
-            f.member("avg_latency_seconds", latency_stats.avg)?;
-            f.member(
-                "detail",
-                nojson::object(|f| {
-                    self.fmt_detail(
-                        f,
-                        &latency_stats,
-                        avg_request_size,
-                        avg_response_size,
-                        max_concurrency,
-                    )
-                }),
-            )?;
-            Ok(())
-        })
+        f.member("elapsed_seconds", duration.as_secs_f64())?;
+        f.member("requests_per_second", rps)?;
+        f.member("avg_latency_seconds", latency_stats.avg)?;
+        f.member(
+            "detail",
+            nojson::object(|f| {
+                self.fmt_detail(
+                    f,
+                    duration,
+                    &latency_stats,
+                    avg_request_size,
+                    avg_response_size,
+                    max_concurrency,
+                )
+            }),
+        )?;
+        Ok(())
     }
-}
 
-impl Stats {
     fn fmt_detail(
         &self,
         f: &mut nojson::JsonObjectFormatter<'_, '_, '_>,
+        duration: Duration,
         latency_stats: &LatencyStats,
         avg_request_size: f64,
         avg_response_size: f64,
@@ -180,74 +1530,610 @@ impl Stats {
                 f.member("response_avg_bytes", avg_response_size.round() as usize)
             }),
         )?;
+        for (name, sizes) in [
+            ("request_size", &self.request_sizes),
+            ("response_size", &self.response_sizes),
+        ] {
+            f.member(
+                name,
+                no_indent_object(|f| {
+                    for p in SIZE_PERCENTILES {
+                        f.member(format!("p{p}_bytes"), size_percentile(sizes, p))?;
+                    }
+                    f.member("max_bytes", sizes.max().unwrap_or_default())
+                }),
+            )?;
+        }
+        f.member(
+            "throughput",
+            no_indent_object(|f| {
+                f.member(
+                    "request_bytes_per_second",
+                    self.calculate_bytes_per_second(self.request_bytes, duration),
+                )?;
+                f.member(
+                    "response_bytes_per_second",
+                    self.calculate_bytes_per_second(self.response_bytes, duration),
+                )
+            }),
+        )?;
         f.member(
             "latency",
             no_indent_object(|f| {
                 f.member("min", latency_stats.min)?;
-                f.member("p25", latency_stats.p25)?;
-                f.member("p50", latency_stats.p50)?;
-                f.member("p75", latency_stats.p75)?;
-                f.member("max", latency_stats.max)
+                for (p, latency) in &latency_stats.percentiles {
+                    f.member(format!("p{p}"), latency)?;
+                }
+                f.member("max", latency_stats.max)?;
+                f.member("stddev", latency_stats.stddev)
             }),
         )?;
         f.member(
             "concurrency",
             no_indent_object(|f| f.member("max", max_concurrency)),
         )?;
+        if self.error_count > 0 {
+            f.member("errors", &self.errors)?;
+        }
+        if let Some(buckets) = &self.latency_by_response_size {
+            f.member(
+                "latency_by_response_size",
+                nojson::array(|f| {
+                    for (&bucket, summary) in buckets {
+                        let (min, max) = size_bucket_range(bucket);
+                        let count = summary.latencies.count();
+                        let avg = summary.latency_sum.as_secs_f64() / count as f64;
+                        f.element(no_indent_object(|f| {
+                            f.member("min_bytes", min)?;
+                            f.member("max_bytes", max)?;
+                            f.member("count", count)?;
+                            f.member("avg", round_to_nanos(avg))?;
+                            for &p in &self.percentiles {
+                                f.member(
+                                    format!("p{p}"),
+                                    latency_percentile(&summary.latencies, p),
+                                )?;
+                            }
+                            Ok(())
+                        }))?;
+                    }
+                    Ok(())
+                }),
+            )?;
+        }
         Ok(())
     }
 
-    fn handle_output(
-        &mut self,
-        output: nojson::RawJsonValue<'_, '_>,
-    ) -> Result<(), nojson::JsonParseError> {
+    fn handle_record(&mut self, record: &Record<'_, '_>) {
+        let start_time = record.start_time;
+        let end_time = record.end_time;
+        let latency = record.latency();
+        let request_byte_size = record.request_byte_size;
+        let response_byte_size = record.response_byte_size;
+
+        self.time_range = Some(match self.time_range {
+            Some((start, end)) => (start.min(start_time), end.max(end_time)),
+            None => (start_time, end_time),
+        });
+        self.concurrency
+            .record(start_time.as_micros() as u64, end_time.as_micros() as u64);
+
+        // Welford's online algorithm for the variance
+        self.latencies.record(latency.as_micros() as u64);
+        self.latency_sum += latency;
+        let delta = latency.as_secs_f64() - self.latency_mean;
+        self.latency_mean += delta / self.latencies.count() as f64;
+        self.latency_m2 += delta * (latency.as_secs_f64() - self.latency_mean);
+
+        self.request_bytes += request_byte_size;
+        self.response_bytes += response_byte_size;
+        self.request_sizes.record(request_byte_size);
+        self.response_sizes.record(response_byte_size);
+        if let Some(buckets) = &mut self.latency_by_response_size {
+            buckets
+                .entry(size_bucket(response_byte_size))
+                .or_default()
+                .record(latency);
+        }
+
+        if let Some(error) = record.error {
+            self.error_count += 1;
+            self.errors.handle_error(error);
+        } else {
+            self.success_count += 1;
+        }
+    }
+}
+
+/// Bench output record with the values used to calculate statistics.
+#[derive(Debug)]
+struct Record<'text, 'raw> {
+    value: nojson::RawJsonValue<'text, 'raw>,
+    start_time: Duration,
+    end_time: Duration,
+    request_byte_size: u64,
+    response_byte_size: u64,
+    error: Option<nojson::RawJsonValue<'text, 'raw>>,
+}
+
+impl<'text, 'raw> Record<'text, 'raw> {
+    /// Parses a record, shifting its timestamps by the given microseconds.
+    fn parse(
+        value: nojson::RawJsonValue<'text, 'raw>,
+        time_shift_micros: i64,
+    ) -> Result<Self, nojson::JsonParseError> {
         // Extract timing and size information from root level
-        let start_time_micros: u64 = output
+        let start_time_micros: u64 = value
             .to_member("start_unix_timestamp_micros")?
             .required()?
             .try_into()?;
-        let end_time_micros: u64 = output
+        let end_time_micros: u64 = value
             .to_member("end_unix_timestamp_micros")?
             .required()?
             .try_into()?;
-        let request_byte_size: usize = output
+        let request_byte_size: u64 = value
             .to_member("request_byte_size")?
             .required()?
             .try_into()?;
-        let response_byte_size: usize = output
+        let response_byte_size: u64 = value
             .to_member("response_byte_size")?
             .required()?
             .try_into()?;
 
-        let start_time = Duration::from_micros(start_time_micros);
-        let end_time = Duration::from_micros(end_time_micros);
+        // Check for success/error based on presence of "result" or "error"
+        let error = value.to_member("error")?.get();
+        if error.is_none() {
+            value.to_member("result")?.required()?;
+        }
+
+        let shift =
+            |micros: u64| Duration::from_micros(micros.saturating_add_signed(time_shift_micros));
+        Ok(Self {
+            value,
+            start_time: shift(start_time_micros),
+            end_time: shift(end_time_micros),
+            request_byte_size,
+            response_byte_size,
+            error,
+        })
+    }
+
+    fn latency(&self) -> Duration {
+        self.end_time.saturating_sub(self.start_time)
+    }
+}
 
-        self.start_end_times.push((start_time, end_time));
-        self.latencies.push(end_time.saturating_sub(start_time));
+/// The N records with the highest latencies, kept in a bounded min-heap.
+#[derive(Debug)]
+struct SlowestCalls {
+    limit: usize,
+    heap: BinaryHeap<Reverse<SlowCall>>,
 
-        self.request_bytes += request_byte_size as u64;
-        self.response_bytes += response_byte_size as u64;
+    // Tie-breaker that prefers earlier records among ones with the same latency
+    seqno: u64,
+}
 
-        // Check for success/error based on presence of "result" or "error"
-        if output.to_member("error")?.get().is_some() {
-            self.error_count += 1;
+impl SlowestCalls {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::new(),
+            seqno: 0,
+        }
+    }
+
+    fn handle_record(&mut self, record: &Record<'_, '_>) {
+        let latency = record.latency();
+        self.seqno += 1;
+        if self.limit == 0 {
+            return;
+        }
+        if self.heap.len() == self.limit
+            && self
+                .heap
+                .peek()
+                .is_some_and(|Reverse(min)| latency <= min.latency)
+        {
+            return;
+        }
+
+        let member = |name| {
+            record
+                .value
+                .to_member(name)
+                .ok()
+                .and_then(|m| m.get())
+                .map(|v| v.extract().into_owned())
+        };
+        self.heap.push(Reverse(SlowCall {
+            latency,
+            seqno: u64::MAX - self.seqno,
+            method: member("method"),
+            id: member("id"),
+            params: member("params"),
+            server: member("server"),
+            start_time: record.start_time,
+            end_time: record.end_time,
+        }));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+}
+
+impl nojson::DisplayJson for SlowestCalls {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        let mut calls = self.heap.iter().map(|Reverse(c)| c).collect::<Vec<_>>();
+        calls.sort_by(|a, b| b.cmp(a));
+        f.array(|f| {
+            for call in calls {
+                f.element(no_indent_object(|f| {
+                    f.member("latency_seconds", call.latency.as_secs_f64())?;
+                    f.member("method", call.method.as_ref().map(|v| v.value()))?;
+                    f.member("id", call.id.as_ref().map(|v| v.value()))?;
+                    f.member("params", call.params.as_ref().map(|v| v.value()))?;
+                    f.member("server", call.server.as_ref().map(|v| v.value()))?;
+                    f.member("start_unix_timestamp_micros", call.start_time.as_micros())?;
+                    f.member("end_unix_timestamp_micros", call.end_time.as_micros())
+                }))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug)]
+struct SlowCall {
+    latency: Duration,
+    seqno: u64,
+    method: Option<nojson::RawJsonOwned>,
+    id: Option<nojson::RawJsonOwned>,
+    params: Option<nojson::RawJsonOwned>,
+    server: Option<nojson::RawJsonOwned>,
+    start_time: Duration,
+    end_time: Duration,
+}
+
+impl PartialEq for SlowCall {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for SlowCall {}
+
+impl PartialOrd for SlowCall {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SlowCall {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.latency, self.seqno).cmp(&(other.latency, other.seqno))
+    }
+}
+
+/// Statistics of records bucketed into fixed-length intervals by their start time.
+///
+/// Intervals are aligned to the start time of the first record in the input
+/// (as bench output is not sorted by start time, earlier records can precede it),
+/// and `start_seconds` is relative to the start of the earliest interval.
+/// Intervals without records are reported with zero counts so that stalls are visible.
+///
+/// Unlike [`Stats`], each interval only keeps the counts and a latency histogram,
+/// so memory usage depends on the number of intervals and groups, not on the number of records.
+#[derive(Debug)]
+struct TimeSeries {
+    interval: Duration,
+    percentiles: Vec<f64>,
+    origin: Option<Duration>,
+    buckets: BTreeMap<(i64, Option<String>), IntervalBucket>,
+
+    // Bucket reported for the intervals without records
+    empty: IntervalBucket,
+}
+
+#[derive(Debug, Default)]
+struct IntervalBucket {
+    success_count: usize,
+    error_count: usize,
+    latency: LatencySummary,
+
+    // Number of requests that were in flight at the end of the interval
+    in_flight: usize,
+}
+
+impl IntervalBucket {
+    fn request_count(&self) -> usize {
+        self.success_count + self.error_count
+    }
+}
+
+impl TimeSeries {
+    fn new(interval: Duration, percentiles: Vec<f64>) -> Self {
+        Self {
+            interval,
+            percentiles,
+            origin: None,
+            buckets: BTreeMap::new(),
+            empty: IntervalBucket::default(),
+        }
+    }
+
+    fn handle_record(&mut self, record: &Record<'_, '_>, group: Option<String>) {
+        let start_index = self.bucket_index(record.start_time);
+        let bucket = self.bucket(group.clone(), start_index);
+        bucket.latency.record(record.latency());
+        if record.error.is_some() {
+            bucket.error_count += 1;
         } else {
-            output.to_member("result")?.required()?;
-            self.success_count += 1;
+            bucket.success_count += 1;
         }
 
-        Ok(())
+        // Count the request as in flight at the end of each interval it spans
+        let mut index = start_index;
+        while self.bucket_start(index + 1) < record.end_time {
+            self.bucket(group.clone(), index).in_flight += 1;
+            index += 1;
+        }
+    }
+
+    fn bucket(&mut self, group: Option<String>, index: i64) -> &mut IntervalBucket {
+        self.buckets.entry((index, group)).or_default()
+    }
+
+    fn bucket_index(&mut self, time: Duration) -> i64 {
+        let origin = *self.origin.get_or_insert(time);
+        let offset = time.as_micros() as i64 - origin.as_micros() as i64;
+        offset.div_euclid(self.interval.as_micros() as i64)
+    }
+
+    fn bucket_start(&self, index: i64) -> Duration {
+        let origin = self.origin.unwrap_or_default().as_micros() as i64;
+        let micros = origin + index * self.interval.as_micros() as i64;
+        Duration::from_micros(micros.max(0) as u64)
+    }
+
+    /// Returns `(start_seconds, group, bucket)` of every interval from the earliest to the latest one
+    /// for all records (`None`) and each group.
+    fn rows(&self) -> impl '_ + Iterator<Item = (f64, Option<&str>, &IntervalBucket)> {
+        let first = self.buckets.keys().next().map_or(0, |(i, _)| *i);
+        let last = self.buckets.keys().next_back().map_or(-1, |(i, _)| *i);
+        let groups = self
+            .buckets
+            .keys()
+            .map(|(_, g)| g.as_deref())
+            .collect::<std::collections::BTreeSet<_>>();
+        (first..=last).flat_map(move |index| {
+            let start_seconds = self.interval.as_secs_f64() * (index - first) as f64;
+            groups.clone().into_iter().map(move |group| {
+                let bucket = self
+                    .buckets
+                    .get(&(index, group.map(str::to_owned)))
+                    .unwrap_or(&self.empty);
+                (round_to_nanos(start_seconds), group, bucket)
+            })
+        })
+    }
+
+    /// Returns the values of [`TimeSeries::lines()`] as flat `(name, value)` pairs for each line.
+    fn metric_rows(&self) -> Vec<MetricRow<'_>> {
+        self.rows()
+            .map(|(start_seconds, group, bucket)| {
+                let latency_stats = bucket.latency.calculate_latency_stats(&self.percentiles);
+                let rps = bucket.request_count() as f64 / self.interval.as_secs_f64();
+
+                let mut metrics = vec![
+                    ("start_seconds".to_owned(), start_seconds),
+                    ("requests_per_second".to_owned(), rps.round()),
+                    ("success".to_owned(), bucket.success_count as f64),
+                    ("error".to_owned(), bucket.error_count as f64),
+                    ("in_flight".to_owned(), bucket.in_flight as f64),
+                ];
+                push_latency_metrics(&mut metrics, &latency_stats);
+                metrics.push(("latency_avg".to_owned(), latency_stats.avg));
+                (group, metrics)
+            })
+            .collect()
+    }
+
+    fn lines(&self) -> impl '_ + Iterator<Item = impl '_ + std::fmt::Display> {
+        let interval = self.interval;
+        let percentiles = &self.percentiles;
+        self.rows().map(move |(start_seconds, group, bucket)| {
+            nojson::object(move |f| {
+                let latency_stats = bucket.latency.calculate_latency_stats(percentiles);
+                let rps = bucket.request_count() as f64 / interval.as_secs_f64();
+
+                if let Some(group) = group {
+                    f.member("group", group)?;
+                }
+                f.member("start_seconds", start_seconds)?;
+                f.member("requests_per_second", rps.round() as usize)?;
+                f.member("success", bucket.success_count)?;
+                f.member("error", bucket.error_count)?;
+                f.member("in_flight", bucket.in_flight)?;
+                f.member(
+                    "latency",
+                    nojson::object(|f| {
+                        f.member("min", latency_stats.min)?;
+                        for (p, latency) in &latency_stats.percentiles {
+                            f.member(format!("p{p}"), latency)?;
+                        }
+                        f.member("max", latency_stats.max)?;
+                        f.member("avg", latency_stats.avg)
+                    }),
+                )
+            })
+        })
+    }
+}
+
+/// Breakdown of errors in bench records.
+///
+/// An `error` with an integer `code` member is counted as a JSON-RPC error.
+/// Any other `error` value (e.g., a string) is counted as a timeout if it mentions one,
+/// or as a transport failure otherwise.
+#[derive(Debug, Default)]
+struct ErrorStats {
+    codes: BTreeMap<i64, usize>,
+    messages: HashMap<(i64, String), usize>,
+    timeout_count: usize,
+    transport_count: usize,
+}
+
+impl ErrorStats {
+    fn handle_error(&mut self, error: nojson::RawJsonValue<'_, '_>) {
+        let code = error
+            .to_member("code")
+            .ok()
+            .and_then(|m| m.get())
+            .and_then(|v| i64::try_from(v).ok());
+        let Some(code) = code else {
+            let text = match error.to_unquoted_string_str() {
+                Ok(s) => s.to_lowercase(),
+                Err(_) => error.as_raw_str().to_lowercase(),
+            };
+            if text.contains("timeout") || text.contains("timed out") {
+                self.timeout_count += 1;
+            } else {
+                self.transport_count += 1;
+            }
+            return;
+        };
+
+        *self.codes.entry(code).or_default() += 1;
+
+        let message = error
+            .to_member("message")
+            .ok()
+            .and_then(|m| m.get())
+            .and_then(|v| v.to_unquoted_string_str().ok())
+            .unwrap_or_default()
+            .into_owned();
+        let key = (code, message);
+        if let Some(count) = self.messages.get_mut(&key) {
+            *count += 1;
+        } else if self.messages.len() < MAX_ERROR_MESSAGES {
+            self.messages.insert(key, 1);
+        }
+    }
+
+    fn top_messages(&self) -> Vec<(&(i64, String), usize)> {
+        let mut messages = self
+            .messages
+            .iter()
+            .map(|(key, &count)| (key, count))
+            .collect::<Vec<_>>();
+        messages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        messages.truncate(TOP_ERROR_MESSAGES);
+        messages
+    }
+}
+
+impl nojson::DisplayJson for ErrorStats {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        f.object(|f| {
+            f.member(
+                "codes",
+                no_indent_object(|f| {
+                    for (code, count) in &self.codes {
+                        f.member(code.to_string(), count)?;
+                    }
+                    Ok(())
+                }),
+            )?;
+            f.member(
+                "top_messages",
+                nojson::array(|f| {
+                    for ((code, message), count) in self.top_messages() {
+                        f.element(no_indent_object(|f| {
+                            f.member("code", code)?;
+                            f.member("message", message)?;
+                            f.member("count", count)
+                        }))?;
+                    }
+                    Ok(())
+                }),
+            )?;
+            f.member("timeout", self.timeout_count)?;
+            f.member("transport", self.transport_count)
+        })
+    }
+}
+
+/// Sweep line over time slots to calculate the max number of concurrent requests.
+///
+/// Slots are one microsecond long at first (which gives the exact result).
+/// Whenever there are more than [`MAX_CONCURRENCY_SLOTS`] non-empty slots, the slot length is doubled.
+/// A coarsened result is the max number of requests overlapping a single slot,
+/// so it may overestimate the true value by requests that share a slot without overlapping in time.
+#[derive(Debug, Default)]
+struct ConcurrencyTracker {
+    slot_shift: u32,
+
+    // Slot => (number of requests starting in it, number of requests ending in it)
+    slots: BTreeMap<u64, (u64, u64)>,
+}
+
+impl ConcurrencyTracker {
+    fn record(&mut self, start_micros: u64, end_micros: u64) {
+        // The last microsecond in which the request was in flight
+        let last_micros = end_micros.saturating_sub(1).max(start_micros);
+
+        self.slots
+            .entry(start_micros >> self.slot_shift)
+            .or_default()
+            .0 += 1;
+        self.slots
+            .entry(last_micros >> self.slot_shift)
+            .or_default()
+            .1 += 1;
+
+        while self.slots.len() > MAX_CONCURRENCY_SLOTS {
+            self.slot_shift += 1;
+            let mut slots = BTreeMap::new();
+            for (slot, (starts, ends)) in std::mem::take(&mut self.slots) {
+                let merged: &mut (u64, u64) = slots.entry(slot >> 1).or_default();
+                merged.0 += starts;
+                merged.1 += ends;
+            }
+            self.slots = slots;
+        }
+    }
+
+    fn max_concurrency(&self) -> usize {
+        let mut in_flight = 0;
+        let mut max = 0;
+        for (starts, ends) in self.slots.values() {
+            in_flight += starts;
+            max = max.max(in_flight);
+            in_flight -= ends;
+        }
+        max as usize
     }
 }
 
 #[derive(Debug, Default)]
 struct LatencyStats {
     min: f64,
-    p25: f64,
-    p50: f64,
-    p75: f64,
+    percentiles: Vec<(f64, f64)>,
     max: f64,
     avg: f64,
+    stddev: f64,
+}
+
+fn push_latency_metrics(metrics: &mut Vec<(String, f64)>, latency_stats: &LatencyStats) {
+    metrics.push(("latency_min".to_owned(), latency_stats.min));
+    for (p, latency) in &latency_stats.percentiles {
+        metrics.push((format!("latency_p{p}"), *latency));
+    }
+    metrics.push(("latency_max".to_owned(), latency_stats.max));
+}
+
+fn round_to_nanos(seconds: f64) -> f64 {
+    (seconds * 1e9).round() / 1e9
 }
 
 fn no_indent_object<F>(f: F) -> impl nojson::DisplayJson
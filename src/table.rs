@@ -0,0 +1,89 @@
+/// Table of text cells rendered as aligned text, Markdown or CSV.
+///
+/// The first column is left-aligned and the others are right-aligned.
+#[derive(Debug)]
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(header: Vec<String>) -> Self {
+        Self {
+            header,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push_row(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    pub fn to_text(&self) -> String {
+        let widths = self.column_widths();
+        let mut text = String::new();
+        for (i, row) in std::iter::once(&self.header).chain(&self.rows).enumerate() {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(j, (cell, &width))| {
+                    if j == 0 {
+                        format!("{cell:<width$}")
+                    } else {
+                        format!("{cell:>width$}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            text.push_str(line.trim_end());
+            text.push('\n');
+            if i == 0 {
+                let total_width = widths.iter().sum::<usize>() + 2 * widths.len().saturating_sub(1);
+                text.push_str(&"-".repeat(total_width));
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let escape = |cell: &String| cell.replace('|', "\\|");
+        let mut text = String::new();
+        let separator = (0..self.header.len())
+            .map(|j| if j == 0 { "---" } else { "---:" }.to_owned())
+            .collect::<Vec<_>>();
+        for row in [&self.header, &separator].into_iter().chain(&self.rows) {
+            let cells = row.iter().map(escape).collect::<Vec<_>>();
+            text.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+        text
+    }
+
+    pub fn to_csv(&self) -> String {
+        let escape = |cell: &String| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.clone()
+            }
+        };
+        let mut text = String::new();
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            let cells = row.iter().map(escape).collect::<Vec<_>>();
+            text.push_str(&cells.join(","));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn column_widths(&self) -> Vec<usize> {
+        let mut widths = vec![0; self.header.len()];
+        for row in std::iter::once(&self.header).chain(&self.rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        widths
+    }
+}
@@ -0,0 +1,634 @@
+use std::cell::Cell;
+use std::time::UNIX_EPOCH;
+
+use crate::{rng::Rng, types::RequestId};
+
+const RAND_STR_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// JSON value containing "{{...}}" placeholders that are expanded for each generated request.
+///
+/// If a whole JSON string is a placeholder, it is replaced with the JSON value of the expansion
+/// (e.g., `"{{rand_int 1 10}}"` becomes a number).
+/// Otherwise, placeholders are replaced with their text within the string.
+#[derive(Debug)]
+pub enum Template {
+    Literal(nojson::RawJsonOwned),
+    Placeholder(Placeholder),
+    String(Vec<Segment>),
+    Array(Vec<Template>),
+    Object(Vec<(String, Template)>),
+}
+
+impl Template {
+    pub fn parse(value: nojson::RawJsonValue<'_, '_>) -> Result<Self, nojson::JsonParseError> {
+        let template = match value.kind() {
+            nojson::JsonValueKind::String => {
+                let text = value.to_unquoted_string_str()?;
+                let segments = parse_segments(&text).map_err(|e| value.invalid(e))?;
+                match &segments[..] {
+                    [] => Self::Literal(value.extract().into_owned()),
+                    [Segment::Text(s)] if *s == text => Self::Literal(value.extract().into_owned()),
+                    [Segment::Placeholder(_)] => {
+                        let Some(Segment::Placeholder(p)) = segments.into_iter().next() else {
+                            unreachable!()
+                        };
+                        Self::Placeholder(p)
+                    }
+                    _ => Self::String(segments),
+                }
+            }
+            nojson::JsonValueKind::Array => {
+                let elements = value
+                    .to_array()?
+                    .map(Self::parse)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if elements.iter().all(|e| matches!(e, Self::Literal(_))) {
+                    Self::Literal(value.extract().into_owned())
+                } else {
+                    Self::Array(elements)
+                }
+            }
+            nojson::JsonValueKind::Object => {
+                let members = value
+                    .to_object()?
+                    .map(|(name, member)| {
+                        Ok((
+                            name.to_unquoted_string_str()?.into_owned(),
+                            Self::parse(member)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, nojson::JsonParseError>>()?;
+                if members.iter().all(|(_, m)| matches!(m, Self::Literal(_))) {
+                    Self::Literal(value.extract().into_owned())
+                } else {
+                    Self::Object(members)
+                }
+            }
+            _ => Self::Literal(value.extract().into_owned()),
+        };
+        Ok(template)
+    }
+
+    pub fn expand<'a>(&'a self, context: &mut Context<'_>) -> Value<'a> {
+        match self {
+            Self::Literal(json) => Value::Json(json.value()),
+            Self::Placeholder(p) => p.expand(context),
+            Self::String(segments) => {
+                let mut text = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Text(s) => text.push_str(s),
+                        Segment::Placeholder(p) => p.expand(context).push_text(&mut text),
+                    }
+                }
+                Value::String(text)
+            }
+            Self::Array(elements) => {
+                Value::Array(elements.iter().map(|e| e.expand(context)).collect())
+            }
+            Self::Object(members) => Value::Object(
+                members
+                    .iter()
+                    .map(|(name, m)| (name.as_str(), m.expand(context)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the column names referred to by the `{{column NAME}}` placeholders.
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            Self::Literal(_) => {}
+            Self::Placeholder(p) => p.collect_columns(columns),
+            Self::String(segments) => {
+                for segment in segments {
+                    if let Segment::Placeholder(p) = segment {
+                        p.collect_columns(columns);
+                    }
+                }
+            }
+            Self::Array(elements) => {
+                for e in elements {
+                    e.collect_columns(columns);
+                }
+            }
+            Self::Object(members) => {
+                for (_, m) in members {
+                    m.collect_columns(columns);
+                }
+            }
+        }
+    }
+}
+
+/// Per-request state referenced by placeholders.
+#[derive(Debug)]
+pub struct Context<'a> {
+    /// Request ID (`None` for notifications)
+    pub id: Option<RequestId>,
+
+    /// Zero-based sequence number of the request
+    pub seq: u64,
+
+    /// Column names and values of the current data record (empty if there is no data file)
+    pub columns: &'a [String],
+    pub row: &'a [String],
+
+    /// Most recent key generated by `{{sequential}}` placeholders (used by `{{latest}}`)
+    pub newest_key: &'a mut Option<i64>,
+
+    pub rng: &'a mut Rng,
+}
+
+#[derive(Debug)]
+pub enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug)]
+pub enum Placeholder {
+    Id,
+    Seq,
+    RandInt {
+        min: i64,
+        max: i64,
+    },
+    Zipf {
+        min: i64,
+        max: i64,
+        skew: f64,
+    },
+    Hotspot {
+        min: i64,
+        max: i64,
+        hot_keys: f64,
+        hot_traffic: f64,
+    },
+    Sequential {
+        min: i64,
+        max: i64,
+        next_offset: Cell<u64>,
+    },
+    Latest {
+        min: i64,
+        max: i64,
+        skew: f64,
+    },
+    RandStr {
+        len: usize,
+    },
+    Uuid,
+    Choice(Vec<nojson::RawJsonOwned>),
+    NowMicros,
+    Column(String),
+}
+
+impl Placeholder {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut args = PlaceholderArgs::new(text.trim());
+        let name = args.next_token().ok_or("empty placeholder")?;
+        let placeholder = match name {
+            "id" => Self::Id,
+            "seq" => Self::Seq,
+            "rand_int" => {
+                let (min, max) = args.next_range(name)?;
+                Self::RandInt { min, max }
+            }
+            "zipf" => {
+                let (min, max) = args.next_range(name)?;
+                let skew = args.next_non_negative(name, "SKEW")?;
+                Self::Zipf { min, max, skew }
+            }
+            "hotspot" => {
+                let (min, max) = args.next_range(name)?;
+                let hot_keys = args.next_fraction(name, "HOT_KEYS")?;
+                let hot_traffic = args.next_fraction(name, "HOT_TRAFFIC")?;
+                Self::Hotspot {
+                    min,
+                    max,
+                    hot_keys,
+                    hot_traffic,
+                }
+            }
+            "sequential" => {
+                let (min, max) = args.next_range(name)?;
+                Self::Sequential {
+                    min,
+                    max,
+                    next_offset: Cell::new(0),
+                }
+            }
+            "latest" => {
+                let (min, max) = args.next_range(name)?;
+                let skew = args.next_non_negative(name, "SKEW")?;
+                Self::Latest { min, max, skew }
+            }
+            "rand_str" => Self::RandStr {
+                len: args.next_parsed(name, "LEN")?,
+            },
+            "uuid" => Self::Uuid,
+            "choice" => {
+                let mut values = Vec::new();
+                while let Some(token) = args.next_token() {
+                    let value = nojson::RawJson::parse(token).map_err(|e| {
+                        format!("{{{{{text}}}}}: invalid JSON value {token:?}: {e}")
+                    })?;
+                    values.push(value.into_owned());
+                }
+                if values.is_empty() {
+                    return Err(format!("{{{{{text}}}}}: at least one value is required"));
+                }
+                Self::Choice(values)
+            }
+            "now_micros" => Self::NowMicros,
+            "column" => {
+                let token = args
+                    .next_token()
+                    .ok_or_else(|| format!("{{{{{name}}}}}: missing NAME argument"))?;
+                if token.starts_with('"') {
+                    let json = nojson::RawJson::parse(token)
+                        .map_err(|e| format!("{{{{{text}}}}}: invalid NAME {token:?}: {e}"))?;
+                    let name = json
+                        .value()
+                        .to_unquoted_string_str()
+                        .map_err(|e| format!("{{{{{text}}}}}: invalid NAME {token:?}: {e}"))?;
+                    Self::Column(name.into_owned())
+                } else {
+                    Self::Column(token.to_owned())
+                }
+            }
+            _ => return Err(format!("unknown placeholder: {{{{{text}}}}}")),
+        };
+        if args.next_token().is_some() {
+            return Err(format!("{{{{{text}}}}}: too many arguments"));
+        }
+        Ok(placeholder)
+    }
+
+    fn expand<'a>(&'a self, context: &mut Context<'_>) -> Value<'a> {
+        match self {
+            Self::Id => match &context.id {
+                None => Value::Null,
+                Some(RequestId::Number(n)) => Value::I64(*n),
+                Some(RequestId::String(s)) => Value::String(s.clone()),
+            },
+            Self::Seq => Value::U64(context.seq),
+            Self::RandInt { min, max } => {
+                let offset = match max.abs_diff(*min).checked_add(1) {
+                    Some(range) => context.rng.next_u64() % range,
+                    None => context.rng.next_u64(),
+                };
+                Value::I64(min.wrapping_add_unsigned(offset))
+            }
+            Self::Zipf { min, max, skew } => {
+                let rank = context.rng.gen_zipf(key_count(*min, *max), *skew);
+                Value::I64(min.wrapping_add_unsigned(rank - 1))
+            }
+            Self::Hotspot {
+                min,
+                max,
+                hot_keys,
+                hot_traffic,
+            } => {
+                let n = key_count(*min, *max);
+                let hot_n = ((n as f64 * hot_keys).ceil() as u64).clamp(1, n);
+                let offset = if hot_n == n || context.rng.gen_bool(*hot_traffic) {
+                    context.rng.next_u64() % hot_n
+                } else {
+                    hot_n + context.rng.next_u64() % (n - hot_n)
+                };
+                Value::I64(min.wrapping_add_unsigned(offset))
+            }
+            Self::Sequential {
+                min,
+                max,
+                next_offset,
+            } => {
+                let offset = next_offset.get();
+                next_offset.set((offset + 1) % key_count(*min, *max));
+                let key = min.wrapping_add_unsigned(offset);
+                *context.newest_key = Some(key);
+                Value::I64(key)
+            }
+            Self::Latest { min, max, skew } => {
+                let newest = context.newest_key.map_or(*max, |k| k.clamp(*min, *max));
+                let rank = context.rng.gen_zipf(key_count(*min, newest), *skew);
+                Value::I64(newest.wrapping_sub_unsigned(rank - 1))
+            }
+            Self::RandStr { len } => Value::String(
+                (0..*len)
+                    .map(|_| {
+                        let i = context.rng.next_u64() as usize % RAND_STR_CHARS.len();
+                        char::from(RAND_STR_CHARS[i])
+                    })
+                    .collect(),
+            ),
+            Self::Uuid => Value::String(gen_uuid(context.rng)),
+            Self::Choice(values) => {
+                let i = context.rng.next_u64() as usize % values.len();
+                Value::Json(values[i].value())
+            }
+            Self::NowMicros => {
+                let now = UNIX_EPOCH.elapsed().unwrap_or_default();
+                Value::U64(now.as_micros() as u64)
+            }
+            Self::Column(name) => context
+                .columns
+                .iter()
+                .position(|c| c == name)
+                .and_then(|i| context.row.get(i))
+                .map_or(Value::Null, |v| Value::String(v.clone())),
+        }
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        if let Self::Column(name) = self {
+            columns.push(name);
+        }
+    }
+}
+
+/// Returns the number of keys in the range `[min, max]` (saturated at `u64::MAX`).
+fn key_count(min: i64, max: i64) -> u64 {
+    max.abs_diff(min).saturating_add(1)
+}
+
+/// Generates a random (version 4) UUID string.
+pub fn gen_uuid(rng: &mut Rng) -> String {
+    let hi = (rng.next_u64() & !0xF000) | 0x4000;
+    let lo = (rng.next_u64() & !(0b11 << 62)) | (0b10 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        hi >> 32,
+        (hi >> 16) & 0xFFFF,
+        hi & 0xFFFF,
+        lo >> 48,
+        lo & 0xFFFF_FFFF_FFFF
+    )
+}
+
+/// Expanded template value.
+#[derive(Debug)]
+pub enum Value<'a> {
+    Null,
+    U64(u64),
+    I64(i64),
+    String(String),
+    Json(nojson::RawJsonValue<'a, 'a>),
+    Array(Vec<Value<'a>>),
+    Object(Vec<(&'a str, Value<'a>)>),
+}
+
+impl Value<'_> {
+    /// Appends the text of this value (strings are unquoted) to `text`.
+    fn push_text(&self, text: &mut String) {
+        match self {
+            Self::String(s) => text.push_str(s),
+            Self::Json(v) if v.kind() == nojson::JsonValueKind::String => {
+                text.push_str(&v.to_unquoted_string_str().expect("infallible"))
+            }
+            _ => text.push_str(&nojson::Json(self).to_string()),
+        }
+    }
+}
+
+impl nojson::DisplayJson for Value<'_> {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        match self {
+            Self::Null => f.value(None::<()>),
+            Self::U64(v) => f.value(v),
+            Self::I64(v) => f.value(v),
+            Self::String(v) => f.value(v),
+            Self::Json(v) => f.value(v),
+            Self::Array(elements) => f.array(|f| f.elements(elements)),
+            Self::Object(members) => f.object(|f| f.members(members.iter().map(|(k, v)| (k, v)))),
+        }
+    }
+}
+
+/// Splits a string into literal text and placeholders.
+fn parse_segments(text: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    for part in split_placeholders(text)? {
+        match (part, segments.last_mut()) {
+            (TextPart::Text(s), Some(Segment::Text(prev))) => prev.push_str(s),
+            (TextPart::Text(s), _) => segments.push(Segment::Text(s.to_owned())),
+            (TextPart::Placeholder(s), _) => {
+                segments.push(Segment::Placeholder(Placeholder::parse(s)?))
+            }
+        }
+    }
+    Ok(segments)
+}
+
+/// Part of a string split by [`split_placeholders()`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextPart<'a> {
+    /// Literal text (adjacent texts are split around escaped "{{")
+    Text(&'a str),
+
+    /// Content between "{{" and "}}"
+    Placeholder(&'a str),
+}
+
+/// Splits a string into literal text and "{{...}}" placeholders.
+///
+/// A placeholder ends at the first "}}" outside of JSON strings, arrays and objects,
+/// so that its arguments can contain "}}". An unclosed "{{" is an error.
+/// "{{{{" is an escape for a literal "{{".
+pub fn split_placeholders(text: &str) -> Result<Vec<TextPart<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        if rest[start..].starts_with("{{{{") {
+            parts.push(TextPart::Text(&rest[..start + 2]));
+            rest = &rest[start + 4..];
+            continue;
+        }
+        let body = &rest[start + 2..];
+        let mut nesting = JsonNesting::default();
+        let (end, _) = body
+            .char_indices()
+            .find(|&(i, c)| nesting.is_top_level(c) && c == '}' && body[i + 1..].starts_with('}'))
+            .ok_or_else(|| format!("unclosed placeholder: {rest}"))?;
+        if start > 0 {
+            parts.push(TextPart::Text(&rest[..start]));
+        }
+        parts.push(TextPart::Placeholder(&body[..end]));
+        rest = &body[end + 2..];
+    }
+    if !rest.is_empty() {
+        parts.push(TextPart::Text(rest));
+    }
+    Ok(parts)
+}
+
+/// Tracks whether each character of a placeholder is inside a JSON string, array or object.
+#[derive(Debug, Default)]
+struct JsonNesting {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonNesting {
+    /// Returns `true` if the next character `c` is outside of JSON strings, arrays and objects.
+    fn is_top_level(&mut self, c: char) -> bool {
+        if self.in_string {
+            match c {
+                _ if self.escaped => self.escaped = false,
+                '\\' => self.escaped = true,
+                '"' => self.in_string = false,
+                _ => {}
+            }
+            return false;
+        }
+        match c {
+            '"' => self.in_string = true,
+            '[' | '{' => self.depth += 1,
+            ']' | '}' if self.depth > 0 => self.depth -= 1,
+            _ => return self.depth == 0,
+        }
+        false
+    }
+}
+
+/// Whitespace-separated arguments of a placeholder, where JSON strings, arrays and objects can contain whitespace.
+#[derive(Debug)]
+struct PlaceholderArgs<'a> {
+    text: &'a str,
+}
+
+impl<'a> PlaceholderArgs<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text }
+    }
+
+    fn next_token(&mut self) -> Option<&'a str> {
+        self.text = self.text.trim_start();
+        if self.text.is_empty() {
+            return None;
+        }
+
+        let mut nesting = JsonNesting::default();
+        let end = self
+            .text
+            .char_indices()
+            .find(|&(_, c)| nesting.is_top_level(c) && c.is_whitespace())
+            .map_or(self.text.len(), |(i, _)| i);
+
+        let (token, rest) = self.text.split_at(end);
+        self.text = rest;
+        Some(token)
+    }
+
+    fn next_range(&mut self, name: &str) -> Result<(i64, i64), String> {
+        let min: i64 = self.next_parsed(name, "MIN")?;
+        let max: i64 = self.next_parsed(name, "MAX")?;
+        if min > max {
+            return Err(format!("{{{{{name}}}}}: MIN must not be greater than MAX"));
+        }
+        Ok((min, max))
+    }
+
+    fn next_non_negative(&mut self, name: &str, arg: &str) -> Result<f64, String> {
+        let v: f64 = self.next_parsed(name, arg)?;
+        if !(v.is_finite() && v >= 0.0) {
+            return Err(format!(
+                "{{{{{name}}}}}: {arg} must be a non-negative number: {v}"
+            ));
+        }
+        Ok(v)
+    }
+
+    fn next_fraction(&mut self, name: &str, arg: &str) -> Result<f64, String> {
+        let v: f64 = self.next_parsed(name, arg)?;
+        if !(0.0..=1.0).contains(&v) {
+            return Err(format!(
+                "{{{{{name}}}}}: {arg} must be between 0.0 and 1.0: {v}"
+            ));
+        }
+        Ok(v)
+    }
+
+    fn next_parsed<T>(&mut self, name: &str, arg: &str) -> Result<T, String>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let token = self
+            .next_token()
+            .ok_or_else(|| format!("{{{{{name}}}}}: missing {arg} argument"))?;
+        token
+            .parse()
+            .map_err(|e| format!("{{{{{name}}}}}: invalid {arg} argument {token:?}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str) -> String {
+        let json = nojson::RawJson::parse(template).expect("valid JSON");
+        let template = Template::parse(json.value()).expect("valid template");
+        let mut rng = Rng::new(0);
+        let mut newest_key = None;
+        let mut context = Context {
+            id: None,
+            seq: 0,
+            columns: &[],
+            row: &[],
+            newest_key: &mut newest_key,
+            rng: &mut rng,
+        };
+        nojson::Json(template.expand(&mut context)).to_string()
+    }
+
+    #[test]
+    fn split_placeholders_with_nested_json() {
+        assert_eq!(
+            split_placeholders(r#"a{{choice {"x":{"y":[1]}}}}b"#),
+            Ok(vec![
+                TextPart::Text("a"),
+                TextPart::Placeholder(r#"choice {"x":{"y":[1]}}"#),
+                TextPart::Text("b"),
+            ])
+        );
+        assert_eq!(
+            split_placeholders(r#"{{choice "a}}b" "\"}}"}}"#),
+            Ok(vec![TextPart::Placeholder(r#"choice "a}}b" "\"}}""#)])
+        );
+        assert!(split_placeholders(r#"{{choice {"x":1}"#).is_err());
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(
+            split_placeholders("a{{{{b}}{{seq}}"),
+            Ok(vec![
+                TextPart::Text("a{{"),
+                TextPart::Text("b}}"),
+                TextPart::Placeholder("seq"),
+            ])
+        );
+        assert_eq!(expand(r#"{"k":"{{{{name}}"}"#), r#"{"k":"{{name}}"}"#);
+        assert_eq!(expand(r#"["{{{{{{seq}}"]"#), r#"["{{0"]"#);
+    }
+
+    #[test]
+    fn expand_choice_with_nested_json() {
+        assert_eq!(
+            expand(r#"{"k":"{{choice {\"a\":{\"b\":1}}}}"}"#),
+            r#"{"k":{"a":{"b":1}}}"#
+        );
+        assert_eq!(expand(r#"["{{choice \"a}}b\"}}!"]"#), r#"["a}}b!"]"#);
+    }
+}
@@ -21,26 +21,97 @@ pub enum RequestId {
     String(String),
 }
 
+impl RequestId {
+    pub fn parse(value: nojson::RawJsonValue<'_, '_>) -> Result<Self, nojson::JsonParseError> {
+        match value.kind() {
+            nojson::JsonValueKind::Integer => Ok(Self::Number(value.try_into()?)),
+            nojson::JsonValueKind::String => Ok(Self::String(value.try_into()?)),
+            _ => {
+                // NOTE: null and float are rejected as the specification does not recommend those
+                Err(value.invalid("id must be an integer or string"))
+            }
+        }
+    }
+}
+
+impl nojson::DisplayJson for RequestId {
+    fn fmt(&self, f: &mut nojson::JsonFormatter<'_, '_>) -> std::fmt::Result {
+        match self {
+            Self::Number(n) => f.value(n),
+            Self::String(s) => f.value(s),
+        }
+    }
+}
+
+/// Probability in the range `[0.0, 1.0]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Probability(pub f64);
+
+impl FromStr for Probability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let p: f64 = s
+            .parse()
+            .map_err(|e| format!("invalid probability {s:?}: {e}"))?;
+        if !(0.0..=1.0).contains(&p) {
+            return Err(format!("probability must be between 0.0 and 1.0: {s:?}"));
+        }
+        Ok(Self(p))
+    }
+}
+
+/// JSON-RPC request object or batch request (array of request objects).
 #[derive(Debug, Clone)]
 pub struct Request {
     pub json: nojson::RawJsonOwned,
+
+    /// ID of the request (`None` for notifications and batch requests)
     pub id: Option<RequestId>,
+
+    /// IDs of the elements if this is a batch request (notifications are excluded)
+    pub batch_ids: Option<Vec<RequestId>>,
 }
 
 impl Request {
     pub fn parse(json_text: String) -> Result<Self, nojson::JsonParseError> {
         let json = nojson::RawJsonOwned::parse(json_text)?;
-        let id = Self::validate_request_and_parse_id(json.value())?;
-        Ok(Self { json, id })
+        let value = json.value();
+        if value.kind() != nojson::JsonValueKind::Array {
+            let id = Self::validate_request_and_parse_id(value)?;
+            return Ok(Self {
+                json,
+                id,
+                batch_ids: None,
+            });
+        }
+
+        let mut ids = Vec::new();
+        for element in batch_elements(value)? {
+            ids.extend(Self::validate_request_and_parse_id(element)?);
+        }
+        Ok(Self {
+            json,
+            id: None,
+            batch_ids: Some(ids),
+        })
+    }
+
+    /// Returns whether the server sends back a response for this request.
+    ///
+    /// A batch request has a response (array) unless all of its elements are notifications.
+    pub fn has_response(&self) -> bool {
+        self.id.is_some() || self.batch_ids.as_ref().is_some_and(|ids| !ids.is_empty())
+    }
+
+    /// Returns the request objects (the elements of a batch request or the request itself).
+    pub fn objects(&self) -> impl Iterator<Item = nojson::RawJsonValue<'_, '_>> {
+        objects(self.json.value())
     }
 
     fn validate_request_and_parse_id(
         value: nojson::RawJsonValue<'_, '_>,
     ) -> Result<Option<RequestId>, nojson::JsonParseError> {
-        if value.kind() == nojson::JsonValueKind::Array {
-            return Err(value.invalid("batch requests are not supported"));
-        }
-
         let mut has_jsonrpc = false;
         let mut has_method = false;
         let mut id = None;
@@ -52,18 +123,7 @@ impl Request {
                     }
                     has_jsonrpc = true;
                 }
-                "id" => {
-                    id = match value.kind() {
-                        nojson::JsonValueKind::Integer => {
-                            Some(RequestId::Number(value.try_into()?))
-                        }
-                        nojson::JsonValueKind::String => Some(RequestId::String(value.try_into()?)),
-                        _ => {
-                            // NOTE: null and float are rejected as the specification does not recommend those
-                            return Err(value.invalid("id must be an integer or string"));
-                        }
-                    };
-                }
+                "id" => id = Some(RequestId::parse(value)?),
                 "method" => {
                     if value.kind() != nojson::JsonValueKind::String {
                         return Err(value.invalid("method must be a string"));
@@ -95,26 +155,38 @@ impl Request {
     }
 }
 
+/// JSON-RPC response object or batch response (array of response objects).
 #[derive(Debug, Clone)]
 pub struct Response {
     pub json: nojson::RawJsonOwned,
-    pub id: Option<RequestId>,
+
+    /// IDs of the response objects in [`Response::objects()`] order (`None` for null IDs)
+    pub ids: Vec<Option<RequestId>>,
 }
 
 impl Response {
     pub fn parse(json_text: String) -> Result<Self, nojson::JsonParseError> {
         let json = nojson::RawJsonOwned::parse(json_text)?;
-        let id = Self::validate_response_and_parse_id(json.value())?;
-        Ok(Self { json, id })
+        let value = json.value();
+        let ids = if value.kind() == nojson::JsonValueKind::Array {
+            batch_elements(value)?
+                .into_iter()
+                .map(Self::validate_response_and_parse_id)
+                .collect::<Result<_, _>>()?
+        } else {
+            vec![Self::validate_response_and_parse_id(value)?]
+        };
+        Ok(Self { json, ids })
+    }
+
+    /// Returns the response objects (the elements of a batch response or the response itself).
+    pub fn objects(&self) -> impl Iterator<Item = nojson::RawJsonValue<'_, '_>> {
+        objects(self.json.value())
     }
 
     fn validate_response_and_parse_id(
         value: nojson::RawJsonValue<'_, '_>,
     ) -> Result<Option<RequestId>, nojson::JsonParseError> {
-        if value.kind() == nojson::JsonValueKind::Array {
-            return Err(value.invalid("batch responses are not supported"));
-        }
-
         let mut has_jsonrpc = false;
         let mut id = None;
         let mut has_result_or_error = false;
@@ -127,15 +199,10 @@ impl Response {
                     }
                     has_jsonrpc = true;
                 }
-                "id" => {
-                    id = match value.kind() {
-                        nojson::JsonValueKind::Integer => {
-                            Some(RequestId::Number(value.try_into()?))
-                        }
-                        nojson::JsonValueKind::String => Some(RequestId::String(value.try_into()?)),
-                        _ => return Err(value.invalid("id must be an integer or string")),
-                    };
+                "id" if value.kind() == nojson::JsonValueKind::Null => {
+                    // Error responses to unparsable requests have null IDs
                 }
+                "id" => id = Some(RequestId::parse(value)?),
                 "result" | "error" => {
                     has_result_or_error = true;
                 }
@@ -155,3 +222,79 @@ impl Response {
         Ok(id)
     }
 }
+
+/// Returns the elements of a batch (a non-empty array of objects).
+fn batch_elements<'text, 'raw>(
+    value: nojson::RawJsonValue<'text, 'raw>,
+) -> Result<Vec<nojson::RawJsonValue<'text, 'raw>>, nojson::JsonParseError> {
+    let elements: Vec<_> = value.to_array()?.collect();
+    if elements.is_empty() {
+        return Err(value.invalid("batch must not be empty"));
+    }
+    if let Some(element) = elements
+        .iter()
+        .find(|e| e.kind() != nojson::JsonValueKind::Object)
+    {
+        return Err(element.invalid("batch elements must be objects"));
+    }
+    Ok(elements)
+}
+
+/// Returns the elements if the value is an array, or the value itself otherwise.
+fn objects<'text, 'raw>(
+    value: nojson::RawJsonValue<'text, 'raw>,
+) -> impl Iterator<Item = nojson::RawJsonValue<'text, 'raw>> {
+    let elements = value.to_array().ok().into_iter().flatten();
+    let single = (value.kind() != nojson::JsonValueKind::Array).then_some(value);
+    elements.chain(single)
+}
+
+/// JSON Pointer (RFC 6901) used to refer to a value inside a JSON document.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JsonPointer(Vec<String>);
+
+impl JsonPointer {
+    pub fn get<'text, 'raw>(
+        &self,
+        mut value: nojson::RawJsonValue<'text, 'raw>,
+    ) -> Option<nojson::RawJsonValue<'text, 'raw>> {
+        for token in &self.0 {
+            value = match value.kind() {
+                nojson::JsonValueKind::Object => value.to_member(token).ok()?.get()?,
+                nojson::JsonValueKind::Array => {
+                    let index: usize = token.parse().ok()?;
+                    value.to_array().ok()?.nth(index)?
+                }
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+impl FromStr for JsonPointer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Self(Vec::new()));
+        }
+        let Some(s) = s.strip_prefix('/') else {
+            return Err(format!("JSON pointer must start with '/': {s:?}"));
+        };
+        let tokens = s
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect();
+        Ok(Self(tokens))
+    }
+}
+
+impl std::fmt::Display for JsonPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in &self.0 {
+            write!(f, "/{}", token.replace('~', "~0").replace('/', "~1"))?;
+        }
+        Ok(())
+    }
+}